@@ -0,0 +1,110 @@
+//! Time the preprocessor on a synthetic book. Run with `cargo bench`.
+//!
+//! The book has `CHAPTERS` chapters spread over `DIRS` directories, each linking to `LINKS`
+//! other chapters, half of them in another directory. The chapters are parsed in parallel: compare
+//! with `RAYON_NUM_THREADS=1 cargo bench` to see what that gains on the machine at hand. It also
+//! counts the allocations made during each run, and with `--features bench` the paths normalized
+//! during each run.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use mdbook_backlinks::Backlinks;
+use mdbook_preprocessor::book::{Book, BookItem, Chapter, SectionNumber};
+use mdbook_preprocessor::config::Config as BookConfig;
+use mdbook_preprocessor::{Preprocessor, PreprocessorContext};
+
+const CHAPTERS: usize = 4000;
+const DIRS: usize = 40;
+const LINKS: usize = 50;
+const RUNS: usize = 10;
+
+/// The system allocator, counting the allocations.
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// How many paths were normalized so far, if the `bench` feature counts them.
+fn normalize_path_calls() -> Option<usize> {
+    #[cfg(feature = "bench")]
+    return Some(mdbook_backlinks::NORMALIZE_PATH_CALLS.load(Ordering::Relaxed));
+    #[cfg(not(feature = "bench"))]
+    None
+}
+
+fn path(i: usize) -> String {
+    format!("d{}/ch{i}.md", i % DIRS)
+}
+
+fn mk_book() -> Book {
+    let items = (0..CHAPTERS)
+        .map(|i| {
+            let content = (1..=LINKS)
+                .map(|n| {
+                    // Spread the targets over the book, in this directory and in others.
+                    let target = (i + n * 7919) % CHAPTERS;
+                    let target = if n % 2 == 0 {
+                        path(target - target % DIRS + i % DIRS)
+                    } else {
+                        path(target)
+                    };
+                    format!("See [chapter {n}](../{target}) for details.\n")
+                })
+                .collect::<String>();
+            let mut ch = Chapter::new(&format!("Chapter {i}"), content, path(i), vec![]);
+            ch.number = Some(SectionNumber::new(vec![i as u32 + 1]));
+            BookItem::Chapter(ch)
+        })
+        .collect();
+    Book::new_with_items(items)
+}
+
+fn main() {
+    let ctx = PreprocessorContext::new(PathBuf::from("."), BookConfig::default(), "html".into());
+    let book = mk_book();
+    let mut allocations = 0;
+    let mut normalizations = None;
+    let mut times: Vec<Duration> = (0..RUNS)
+        .map(|_| {
+            let book = book.clone();
+            let start_allocations = ALLOCATIONS.load(Ordering::Relaxed);
+            let start_normalizations = normalize_path_calls();
+            let start = Instant::now();
+            let book = black_box(Backlinks.run(&ctx, book).unwrap());
+            let elapsed = start.elapsed();
+            allocations = ALLOCATIONS.load(Ordering::Relaxed) - start_allocations;
+            normalizations = normalize_path_calls()
+                .zip(start_normalizations)
+                .map(|(n, s)| n - s);
+            drop(book);
+            elapsed
+        })
+        .collect();
+    times.sort();
+    println!(
+        "{CHAPTERS} chapters with {LINKS} links each: median {:?}, min {:?} over {RUNS} runs, \
+         {allocations} allocations per run",
+        times[RUNS / 2],
+        times[0]
+    );
+    if let Some(normalizations) = normalizations {
+        println!("{normalizations} paths normalized per run");
+    }
+}
@@ -0,0 +1,5518 @@
+//! A preprocessor for mdbook that adds backlinks to each chapter. The link collection is exposed
+//! for other preprocessors through [`collect_backlinks`].
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Display;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::OnceLock;
+
+use anyhow::{Context, anyhow, bail};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use handlebars::Handlebars;
+use itertools::Itertools;
+use path_normalizer::NormalizeError;
+use rayon::prelude::*;
+use serde::{Deserialize, Deserializer, Serialize};
+
+use mdbook_markdown::pulldown_cmark::{
+    Alignment, BlockQuoteKind, CowStr, Event, HeadingLevel, LinkType, Tag, TagEnd, TextMergeStream,
+};
+use mdbook_preprocessor::book::{Book, BookItem, Chapter};
+use mdbook_preprocessor::config::Config as BookConfig;
+use mdbook_preprocessor::errors::Error;
+use mdbook_preprocessor::{Preprocessor, PreprocessorContext};
+
+/// Environment variable that enables tracing how the links of the book are resolved.
+const LOG_VAR: &str = "MDBOOK_BACKLINKS_LOG";
+
+fn log_enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| std::env::var_os(LOG_VAR).is_some_and(|v| !v.is_empty()))
+}
+
+/// Like `eprintln!`, but only when `MDBOOK_BACKLINKS_LOG` is set.
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        if log_enabled() {
+            eprintln!("[backlinks] {}", format_args!($($arg)*));
+        }
+    };
+}
+
+/// Helper to build a pulldown_cmark document.
+#[derive(Default)]
+struct MarkdownBuilder<'a>(Vec<Event<'a>>);
+
+impl<'a> MarkdownBuilder<'a> {
+    pub fn write_to_string(self, s: &mut String) {
+        let _ = pulldown_cmark_to_cmark::cmark(self.0.into_iter(), s);
+    }
+
+    /// Use when none of the other helpers suffice.
+    pub fn event(&mut self, event: Event<'a>) {
+        self.0.push(event);
+    }
+
+    /// Emit a piece of text.
+    pub fn text(&mut self, txt: impl Into<CowStr<'a>>) {
+        self.event(Event::Text(txt.into()));
+    }
+
+    /// Start a tag before the input closure and end it afterwards.
+    pub fn tag(&mut self, tag: Tag<'a>, f: impl FnOnce(&mut Self)) {
+        let end = tag.to_end();
+        self.event(Event::Start(tag));
+        f(self);
+        self.event(Event::End(end));
+    }
+
+    pub fn heading(
+        &mut self,
+        level: HeadingLevel,
+        id: Option<CowStr<'a>>,
+        f: impl FnOnce(&mut Self),
+    ) {
+        self.tag(
+            Tag::Heading {
+                level,
+                id,
+                classes: vec![],
+                attrs: vec![],
+            },
+            f,
+        )
+    }
+    pub fn simple_link(&mut self, dest_url: impl Into<CowStr<'a>>, f: impl FnOnce(&mut Self)) {
+        self.link(dest_url, "", f)
+    }
+    pub fn link(
+        &mut self,
+        dest_url: impl Into<CowStr<'a>>,
+        title: impl Into<CowStr<'a>>,
+        f: impl FnOnce(&mut Self),
+    ) {
+        self.tag(
+            Tag::Link {
+                link_type: LinkType::Inline,
+                dest_url: dest_url.into(),
+                title: title.into(),
+                id: "".into(),
+            },
+            f,
+        )
+    }
+}
+
+/// Helper struct to make sure we normalize paths before comparing them.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NormalizedPathBuf(PathBuf);
+
+impl std::ops::Deref for NormalizedPathBuf {
+    type Target = Path;
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+impl NormalizedPathBuf {
+    /// The directory containing the chapter at this path. A path without a parent, like `.` or
+    /// `/`, is its own directory.
+    fn dir(&self) -> &Path {
+        self.0.parent().unwrap_or(&self.0)
+    }
+
+    /// The path relative to the book's source directory, without the leading `./`.
+    pub fn relative_to_root(&self) -> &Path {
+        self.0.strip_prefix(".").unwrap_or(&self.0)
+    }
+
+    /// The path with its letters lowercased. Lowercasing doesn't affect normalization.
+    fn to_lowercase(&self) -> NormalizedPathBuf {
+        match self.0.to_str() {
+            Some(path) => NormalizedPathBuf(path.to_lowercase().into()),
+            None => self.clone(),
+        }
+    }
+}
+impl AsRef<Path> for NormalizedPathBuf {
+    fn as_ref(&self) -> &Path {
+        self.0.as_ref()
+    }
+}
+
+/// How many times [`PathNormalizeExt::normalize_path`] was called, for the benchmark.
+#[cfg(feature = "bench")]
+pub static NORMALIZE_PATH_CALLS: std::sync::atomic::AtomicUsize =
+    std::sync::atomic::AtomicUsize::new(0);
+
+/// Extension trait to add lexical normalization to paths.
+pub trait PathNormalizeExt {
+    /// Normalize a path lexically, resolving `.` and `..` without accessing the filesystem.
+    fn normalize_path(&self) -> Result<NormalizedPathBuf, NormalizeError>;
+}
+impl PathNormalizeExt for Path {
+    fn normalize_path(&self) -> Result<NormalizedPathBuf, NormalizeError> {
+        #[cfg(feature = "bench")]
+        NORMALIZE_PATH_CALLS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let path = if self.is_relative() {
+            // Without the leading `.`, the normalizer isn't actually normalizing for relative paths.
+            &PathBuf::from(".").join(self)
+        } else {
+            self
+        };
+        Ok(NormalizedPathBuf(
+            path_normalizer::PathNormalizeExt::normalize_path(path)?,
+        ))
+    }
+}
+
+/// The information about a chapter that we display in links to it. This is shared between all
+/// the links to a chapter to avoid cloning it for each link.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ChapterInfo {
+    /// Section number of the chapter.
+    pub number: Option<Vec<u32>>,
+    /// Name of the chapter.
+    pub name: String,
+    /// Normalized path of the chapter.
+    pub path: NormalizedPathBuf,
+    /// Text of the links to the chapter: its name or its first H1, depending on `title_source`.
+    pub title: String,
+    /// Names of the part and of the chapters the chapter is nested in, outermost first.
+    pub ancestors: Vec<String>,
+}
+
+/// A link from one chapter to another, as recorded on the receiving chapter.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Backlink {
+    /// The linking chapter.
+    pub source: Rc<ChapterInfo>,
+    /// Fragment of the link, designating a location inside the receiving chapter.
+    pub fragment: Option<String>,
+    /// Text of the link. Only recorded with `link_text = "anchor"`.
+    pub anchor_text: String,
+    /// Text of the link as Markdown, keeping its inline code and emphasis. Only recorded with
+    /// `link_text = "anchor"`, and empty for wikilinks.
+    pub anchor_markdown: String,
+    /// Title of the link. Only recorded with `link_titles = true`.
+    pub title: String,
+    /// Relationship of the link, from a `name:` prefix of its title. Only recorded for the names
+    /// of `relationships`.
+    pub relationship: Option<String>,
+    /// Anchor of the heading of the linking chapter that the link is under. Only recorded with
+    /// `anchor_to_source = true`.
+    pub source_anchor: Option<String>,
+}
+
+impl Backlink {
+    /// The fields of the backlink other than its fragment.
+    fn without_fragment(&self) -> (&Rc<ChapterInfo>, &str, &str, Option<&str>) {
+        (
+            &self.source,
+            &self.anchor_text,
+            &self.title,
+            self.relationship.as_deref(),
+        )
+    }
+}
+
+/// Configuration read from the `[preprocessor.backlinks]` table of `book.toml`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+struct Config {
+    /// Text of the heading above the list of backlinks. An empty string omits the heading.
+    heading: String,
+    /// Heading text per top-level directory of the chapters, e.g. one per language. Chapters
+    /// outside of these directories use `heading`.
+    headings: HashMap<String, String>,
+    /// Sentence between the heading and the list of backlinks. An empty string omits it.
+    intro_text: String,
+    /// Add the block to chapters without backlinks too, with `empty_text` instead of the list.
+    show_empty: bool,
+    /// Sentence shown in place of the list of backlinks of chapters that have none.
+    empty_text: String,
+    /// With the HTML renderer, id of the backlinks heading, for linking to it. A numeric suffix is
+    /// added if the chapter already has a heading with this id. An empty string disables the id.
+    heading_id: String,
+    /// Level of the heading, written either as a number (`4`) or as a tag name (`"h4"`).
+    #[serde(deserialize_with = "deserialize_heading_level")]
+    heading_level: HeadingLevel,
+    /// Where to place the backlinks block in the chapter.
+    position: Position,
+    /// How the block looks with the markdown renderer.
+    markdown_style: MarkdownStyle,
+    /// Separate the backlinks block from the chapter with a horizontal rule.
+    separator: bool,
+    /// Only add backlinks to chapters that contain a `{{#backlinks}}` placeholder.
+    placeholder_only: bool,
+    /// Start the block with a hidden comment listing the linking chapters in JSON, for scripts.
+    embed_metadata: bool,
+    /// Leave the book unchanged, and report the number of backlinks of each chapter instead.
+    report_only: bool,
+    /// Glob patterns of the chapters that don't get a backlinks block. They still count as
+    /// backlinks of other chapters. Relative to the book's source directory.
+    #[serde(deserialize_with = "deserialize_globs")]
+    exclude: GlobSet,
+    /// If set, glob patterns of the only chapters whose links are collected and that get a
+    /// backlinks block. Relative to the book's source directory. `exclude` takes precedence.
+    #[serde(deserialize_with = "deserialize_optional_globs")]
+    include: Option<GlobSet>,
+    /// Which chapters count as backlinks of a chapter.
+    scope: Scope,
+    /// Warn about links to chapters that don't exist.
+    check_broken: bool,
+    /// Fail the build if there are links to chapters that don't exist.
+    fail_on_broken: bool,
+    /// Also list the chapters that each chapter links to.
+    forward_links: bool,
+    /// Text of the heading above the list of forward links. An empty string omits the heading.
+    forward_heading: String,
+    /// List the chapters that link to many of the same chapters as this one.
+    related: bool,
+    /// Maximum number of related chapters to list.
+    related_count: usize,
+    /// Text of the heading above the list of related chapters.
+    related_heading: String,
+    /// File to write the link graph to, in the Graphviz DOT format. Relative to the book's
+    /// source directory.
+    graph_output: Option<PathBuf>,
+    /// File to write the backlinks of every chapter to, in JSON. Relative to the book's source
+    /// directory.
+    json_output: Option<PathBuf>,
+    /// File to write the number of links to every chapter and of chapters they come from to, in
+    /// JSON. Relative to the book's source directory.
+    counts_output: Option<PathBuf>,
+    /// Show the number of backlinks in the heading.
+    #[serde(deserialize_with = "deserialize_show_count")]
+    show_count: ShowCount,
+    /// Order of the list of backlinks.
+    sort: SortOrder,
+    /// Whether the lists of the block are bulleted or numbered.
+    list_style: ListStyle,
+    /// Whether the backlinks are shown as a list or a table.
+    layout: Layout,
+    /// What the block is wrapped in.
+    container: Container,
+    /// The kind of callout the blockquote is rendered as, like `> [!NOTE]`.
+    callout: Callout,
+    /// What the urls of the links in the block are relative to.
+    link_base: LinkBase,
+    /// What to do with the `.md` extension of the urls of the links in the block.
+    link_extension: LinkExtension,
+    /// Which links to the chapter are merged into a single backlink.
+    dedup: Dedup,
+    /// What to use as the text of each backlink.
+    link_text: LinkText,
+    /// Prefix the name of each linking chapter with the names of its part and parent chapters, as
+    /// in "Part > Section > Chapter".
+    breadcrumb: bool,
+    /// Text of each backlink, with placeholders like `{name}` replaced for each backlink. Replaces
+    /// the label built from `link_text`, `breadcrumb` and `show_numbers` when set.
+    #[serde(deserialize_with = "deserialize_item_format")]
+    item_format: Option<ItemFormat>,
+    /// The syntaxes of the links that count as backlinks.
+    link_types: Vec<LinkKind>,
+    /// Also count images embedded from other chapters as links.
+    count_images: bool,
+    /// Show the titles of the links as tooltips of the backlinks.
+    link_titles: bool,
+    /// Point each backlink to the heading of the linking chapter that the link is under.
+    anchor_to_source: bool,
+    /// Also collect wiki-style links, i.e. `[[target]]` or `[[target|label]]`.
+    wikilinks: bool,
+    /// Also collect the links of raw HTML `<a href="...">` tags.
+    html_links: bool,
+    /// Resolve links starting with `/` relative to the book's source directory instead of the
+    /// filesystem root.
+    root_relative: bool,
+    /// Match link targets to chapters regardless of case.
+    case_insensitive: bool,
+    /// File names of the chapters that links to a directory point to, in order of preference.
+    index_names: Vec<PathBuf>,
+    /// Extensions of the chapter sources, like `.md`. Wikilinks without an extension get the first
+    /// one.
+    extensions: Vec<String>,
+    /// Resolve the links that omit the extension of a chapter source, like `[x](chapter)`, by
+    /// trying each of the `extensions` in turn.
+    resolve_extensionless: bool,
+    /// Collect the links of the files included with `{{#include}}` as links of the including
+    /// chapter.
+    resolve_includes: bool,
+    /// Warn about draft chapters whose links are ignored.
+    warn_drafts: bool,
+    /// Warn about links that point outside of the book's source directory.
+    warn_escapes: bool,
+    /// Warn about links with an empty destination, like `[x]()`.
+    warn_empty_links: bool,
+    /// Count the links from a chapter to itself as backlinks.
+    include_self: bool,
+    /// Record the links to an anchor of the same chapter, like `[x](#usage)`, as links from the
+    /// chapter to itself instead of ignoring them.
+    same_page_links: bool,
+    /// Where to take the text of the links to a chapter from.
+    title_source: TitleSource,
+    /// Group backlinks by the top-level chapter they belong to.
+    group_by_part: bool,
+    /// Relationships that links can be tagged with, whose backlinks are listed in their own
+    /// section, in this order.
+    relationships: Vec<Relationship>,
+    /// Merge the backlinks from the same chapter to different anchors into a single entry, with
+    /// a sub-item per anchor.
+    group_anchors: bool,
+    /// Warn about chapters that no other chapter links to.
+    check_orphans: bool,
+    /// Fail the build if there are chapters that no other chapter links to.
+    fail_on_orphans: bool,
+    /// File to write the list of chapters that no other chapter links to. Relative to the
+    /// book's source directory.
+    orphans_output: Option<PathBuf>,
+    /// Chapters that are not reported as orphans even if no other chapter links to them.
+    orphan_ignore: Vec<PathBuf>,
+    /// Number of most linked chapters to report. 0 disables the report.
+    hub_report: usize,
+    /// File to write the most linked chapters report to, relative to the book's source directory.
+    /// The report goes to stderr if this isn't set.
+    hub_report_output: Option<PathBuf>,
+    /// Prefix backlinks with the section number of the linking chapter.
+    show_numbers: bool,
+    /// What goes between the section number and the rest of the label with `show_numbers`.
+    number_separator: String,
+    /// Show how many times a chapter links to this one when that's more than once.
+    show_repeat_count: bool,
+    /// Mark the backlinks from chapters that this one links to as well with `↔`.
+    mark_mutual: bool,
+    /// What to add to the labels of backlinks from different chapters that would be identical.
+    disambiguate: Disambiguate,
+    /// Maximum number of backlinks to show. `0` means no limit.
+    max_backlinks: usize,
+    /// How many links away the listed chapters can be. With `2`, the chapters linking to each
+    /// linking chapter are nested under it.
+    depth: usize,
+    /// With the HTML renderer, CSS class of a `<div>` wrapping the block. The forward links and
+    /// related pages headings get ids prefixed with it. An empty string disables both.
+    css_class: String,
+    /// With the HTML renderer, wrap the backlinks in a collapsible `<details>` element.
+    collapsible: bool,
+    /// Renderers to add backlinks for.
+    renderers: Vec<String>,
+    /// Add a chapter at this path to the end of the book, listing the backlinks of every chapter.
+    index_chapter: Option<PathBuf>,
+    /// Name of the `index_chapter`, also used as its title.
+    index_title: String,
+    /// Handlebars template to render the block with instead of the built-in layout. Relative to
+    /// the book's root directory.
+    template: Option<PathBuf>,
+    /// The contents of `template`. Not read from the configuration.
+    #[serde(skip)]
+    template_source: Option<String>,
+    /// The book's source directory. Not read from the configuration.
+    #[serde(skip)]
+    src_dir: PathBuf,
+    /// The renderer we're running for. Not read from the configuration.
+    #[serde(skip)]
+    renderer: String,
+}
+
+/// A kind of link, whose backlinks get their own section.
+#[derive(Debug, Clone, Deserialize)]
+struct Relationship {
+    /// What link titles start with, followed by a `:`.
+    name: String,
+    /// Text of the heading of the section.
+    heading: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Position {
+    Top,
+    Bottom,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ShowCount {
+    Off,
+    /// The number of backlinks, e.g. "Backlinks (3)".
+    Total,
+    /// The number of links and of chapters they come from, e.g. "Backlinks (5 from 3 pages)".
+    Detailed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum LinkBase {
+    /// Relative to the directory of the chapter the link is in.
+    Chapter,
+    /// Relative to the book's source directory.
+    Root,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum LinkExtension {
+    /// Keep the `.md` extension, which mdbook rewrites for its HTML renderer.
+    Md,
+    /// Replace `.md` with `.html`.
+    Html,
+    /// Remove the `.md` extension.
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ListStyle {
+    Bullet,
+    Numbered,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Scope {
+    /// All the chapters of the book.
+    Book,
+    /// The chapters with the same top-level section number. Unnumbered chapters have no part.
+    Part,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum MarkdownStyle {
+    /// The same block as with the other renderers.
+    Blockquote,
+    /// A `##` heading and a list, without a separator or blockquote.
+    Plain,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Layout {
+    List,
+    /// A table of the linking chapters and their section numbers.
+    Table,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Container {
+    /// A blockquote, after a separator.
+    Blockquote,
+    /// The heading and lists at the top level, after a separator.
+    Section,
+    /// Only the lists, without the backlinks heading or a separator.
+    None,
+}
+
+/// The GitHub-style callouts, which some themes style.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Callout {
+    /// A plain blockquote.
+    None,
+    Note,
+    Tip,
+    Important,
+    Warning,
+    Caution,
+}
+
+impl Callout {
+    fn kind(self) -> Option<BlockQuoteKind> {
+        match self {
+            Callout::None => None,
+            Callout::Note => Some(BlockQuoteKind::Note),
+            Callout::Tip => Some(BlockQuoteKind::Tip),
+            Callout::Important => Some(BlockQuoteKind::Important),
+            Callout::Warning => Some(BlockQuoteKind::Warning),
+            Callout::Caution => Some(BlockQuoteKind::Caution),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SortOrder {
+    /// By section number of the linking chapter.
+    Number,
+    /// Alphabetically by name of the linking chapter.
+    Name,
+    /// By path of the linking chapter.
+    Path,
+    /// In the order the links appear in the book.
+    Document,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Dedup {
+    /// One backlink per identical link, i.e. same chapter, fragment, text and title.
+    Full,
+    /// One backlink per set of links that differ only by their fragment.
+    Anchor,
+    /// One backlink per linking chapter, for its first link.
+    Path,
+    /// One backlink per link.
+    None,
+}
+
+/// The syntaxes of Markdown links.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum LinkKind {
+    /// `[text](url)`.
+    Inline,
+    /// `[text][label]`.
+    Reference,
+    /// `[label][]`.
+    Collapsed,
+    /// `[label]`.
+    Shortcut,
+    /// `<url>`.
+    Autolink,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum LinkText {
+    /// The name of the linking chapter.
+    Chapter,
+    /// The text of the link in the linking chapter.
+    Anchor,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Disambiguate {
+    /// Leave identical labels as they are.
+    None,
+    /// Add the section number of the linking chapter, or its path if it has none.
+    Number,
+    /// Add the path of the linking chapter.
+    Path,
+    /// Prefix the label with the name of the directory of the linking chapter, as in
+    /// "a/Overview".
+    Parent,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum TitleSource {
+    /// The name of the chapter in `SUMMARY.md`.
+    Name,
+    /// The first H1 heading of the chapter, falling back to its name.
+    Heading,
+}
+
+/// A parsed `item_format`, like `"{number} {name} ({count})"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ItemFormat(Vec<FormatPiece>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum FormatPiece {
+    Text(String),
+    /// The name of the linking chapter.
+    Name,
+    /// The section number of the linking chapter, empty if it has none.
+    Number,
+    /// The url of the linking chapter.
+    Url,
+    /// How many links the backlink stands for.
+    Count,
+    /// The heading of the anchor the link points to, empty if it has none.
+    Anchor,
+}
+
+impl ItemFormat {
+    const PLACEHOLDERS: [(&str, FormatPiece); 5] = [
+        ("name", FormatPiece::Name),
+        ("number", FormatPiece::Number),
+        ("url", FormatPiece::Url),
+        ("count", FormatPiece::Count),
+        ("anchor", FormatPiece::Anchor),
+    ];
+
+    /// Parse a format, where `{{` and `}}` stand for literal braces.
+    fn parse(format: &str) -> Result<Self, String> {
+        let mut pieces = Vec::new();
+        let mut text = String::new();
+        let mut rest = format;
+        while let Some(i) = rest.find(['{', '}']) {
+            text.push_str(&rest[..i]);
+            let brace = &rest[i..i + 1];
+            rest = &rest[i + 1..];
+            if let Some(after) = rest.strip_prefix(brace) {
+                text.push_str(brace);
+                rest = after;
+                continue;
+            }
+            let Some((name, after)) = rest.split_once('}').filter(|_| brace == "{") else {
+                return Err(format!(
+                    "unmatched `{brace}` in item_format `{format}`, write `{brace}{brace}` for a \
+                     literal brace"
+                ));
+            };
+            let Some((_, piece)) = Self::PLACEHOLDERS.iter().find(|(n, _)| *n == name) else {
+                let known = Self::PLACEHOLDERS
+                    .iter()
+                    .map(|(n, _)| format!("`{{{n}}}`"))
+                    .join(", ");
+                return Err(format!(
+                    "unknown placeholder `{{{name}}}` in item_format `{format}`, expected one of \
+                     {known}"
+                ));
+            };
+            if !text.is_empty() {
+                pieces.push(FormatPiece::Text(std::mem::take(&mut text)));
+            }
+            pieces.push(piece.clone());
+            rest = after;
+        }
+        text.push_str(rest);
+        if !text.is_empty() {
+            pieces.push(FormatPiece::Text(text));
+        }
+        Ok(ItemFormat(pieces))
+    }
+
+    fn render(&self, backlink: &Backlink, url: &str, count: usize, anchor: &str) -> String {
+        self.0
+            .iter()
+            .map(|piece| match piece {
+                FormatPiece::Text(text) => text.clone(),
+                FormatPiece::Name => backlink.source.title.clone(),
+                FormatPiece::Number => match &backlink.source.number {
+                    Some(number) if !number.is_empty() => section_number(number),
+                    _ => String::new(),
+                },
+                FormatPiece::Url => url.to_owned(),
+                FormatPiece::Count => count.to_string(),
+                FormatPiece::Anchor => anchor.to_owned(),
+            })
+            .collect()
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            heading: "Backlinks".to_owned(),
+            headings: HashMap::new(),
+            intro_text: String::new(),
+            show_empty: false,
+            empty_text: "No pages link here yet.".to_owned(),
+            heading_id: "backlinks".to_owned(),
+            heading_level: HeadingLevel::H4,
+            separator: true,
+            markdown_style: MarkdownStyle::Blockquote,
+            placeholder_only: false,
+            embed_metadata: false,
+            report_only: false,
+            exclude: GlobSet::empty(),
+            include: None,
+            position: Position::Bottom,
+            scope: Scope::Book,
+            check_broken: false,
+            fail_on_broken: false,
+            forward_links: false,
+            forward_heading: "Links".to_owned(),
+            related: false,
+            related_count: 3,
+            related_heading: "Related pages".to_owned(),
+            graph_output: None,
+            json_output: None,
+            counts_output: None,
+            show_count: ShowCount::Off,
+            sort: SortOrder::Number,
+            list_style: ListStyle::Bullet,
+            layout: Layout::List,
+            container: Container::Blockquote,
+            callout: Callout::None,
+            link_base: LinkBase::Chapter,
+            link_extension: LinkExtension::Md,
+            dedup: Dedup::Full,
+            link_text: LinkText::Chapter,
+            breadcrumb: false,
+            item_format: None,
+            link_types: vec![
+                LinkKind::Inline,
+                LinkKind::Reference,
+                LinkKind::Collapsed,
+                LinkKind::Shortcut,
+                LinkKind::Autolink,
+            ],
+            count_images: false,
+            link_titles: false,
+            anchor_to_source: false,
+            wikilinks: false,
+            html_links: false,
+            root_relative: false,
+            case_insensitive: false,
+            index_names: vec!["README.md".into(), "index.md".into()],
+            extensions: vec![".md".into()],
+            resolve_extensionless: false,
+            resolve_includes: false,
+            warn_drafts: false,
+            warn_escapes: false,
+            warn_empty_links: false,
+            include_self: false,
+            same_page_links: false,
+            title_source: TitleSource::Name,
+            group_by_part: false,
+            relationships: Vec::new(),
+            group_anchors: false,
+            check_orphans: false,
+            fail_on_orphans: false,
+            orphans_output: None,
+            orphan_ignore: Vec::new(),
+            hub_report: 0,
+            hub_report_output: None,
+            show_numbers: false,
+            number_separator: " ".to_owned(),
+            show_repeat_count: false,
+            mark_mutual: false,
+            disambiguate: Disambiguate::None,
+            max_backlinks: 0,
+            depth: 1,
+            css_class: "mdbook-backlinks".to_owned(),
+            collapsible: false,
+            renderers: vec!["html".to_owned()],
+            index_chapter: None,
+            index_title: "Backlinks index".to_owned(),
+            template: None,
+            template_source: None,
+            src_dir: PathBuf::new(),
+            renderer: "html".to_owned(),
+        }
+    }
+}
+
+impl Config {
+    fn from_book_config(config: &BookConfig) -> Result<Self, Error> {
+        Ok(config.get("preprocessor.backlinks")?.unwrap_or_default())
+    }
+
+    fn from_context(ctx: &PreprocessorContext) -> Result<Self, Error> {
+        let mut config = Config::from_book_config(&ctx.config)?;
+        config.renderer = ctx.renderer.clone();
+        config.src_dir = ctx.root.join(&ctx.config.book.src);
+        if let Some(template) = &config.template {
+            let path = ctx.root.join(template);
+            let source = std::fs::read_to_string(&path)
+                .with_context(|| format!("failed to read template `{}`", path.display()))?;
+            config.template_source = Some(source);
+        }
+        Ok(config)
+    }
+
+    /// The key of the given chapter in the maps of [`LinkGraph`].
+    fn path_key(&self, path: &NormalizedPathBuf) -> NormalizedPathBuf {
+        if self.case_insensitive {
+            path.to_lowercase()
+        } else {
+            path.clone()
+        }
+    }
+
+    /// The `extensions` of the chapter sources, without their leading dot.
+    fn source_extensions(&self) -> impl Iterator<Item = &str> {
+        self.extensions
+            .iter()
+            .map(|ext| ext.strip_prefix('.').unwrap_or(ext))
+    }
+
+    /// Whether the given path has the extension of a chapter source.
+    fn is_source(&self, path: &Path) -> bool {
+        path.extension()
+            .is_some_and(|ext| self.source_extensions().any(|source_ext| ext == source_ext))
+    }
+
+    /// The url of a link in the block, with its extension changed according to `link_extension`.
+    fn link_url(&self, url: String) -> String {
+        let stem = self
+            .source_extensions()
+            .find_map(|ext| url.strip_suffix(ext)?.strip_suffix('.'));
+        match (self.link_extension, stem) {
+            (LinkExtension::Html, Some(stem)) => format!("{stem}.html"),
+            (LinkExtension::None, Some(stem)) => stem.to_owned(),
+            _ => url,
+        }
+    }
+
+    /// The heading of the backlinks of the given chapter.
+    fn heading_for(&self, path: &NormalizedPathBuf) -> &str {
+        path.relative_to_root()
+            .components()
+            .next()
+            .and_then(|dir| self.headings.get(dir.as_os_str().to_str()?))
+            .unwrap_or(&self.heading)
+    }
+
+    /// Whether the given chapter matches `include`, if set.
+    fn in_scope(&self, path: &NormalizedPathBuf) -> bool {
+        self.include
+            .as_ref()
+            .is_none_or(|include| include.is_match(path.relative_to_root()))
+    }
+
+    /// Whether the links of the given type count as backlinks, according to `link_types`.
+    fn counts_link_type(&self, link_type: LinkType) -> bool {
+        let kind = match link_type {
+            LinkType::Inline => LinkKind::Inline,
+            LinkType::Reference | LinkType::ReferenceUnknown => LinkKind::Reference,
+            LinkType::Collapsed | LinkType::CollapsedUnknown => LinkKind::Collapsed,
+            LinkType::Shortcut | LinkType::ShortcutUnknown => LinkKind::Shortcut,
+            LinkType::Autolink | LinkType::Email => LinkKind::Autolink,
+            // Wikilinks have their own option.
+            LinkType::WikiLink { .. } => return true,
+        };
+        self.link_types.contains(&kind)
+    }
+
+    /// The relationship a link title starts with, e.g. `see-also` for `"see-also: Details"`, and
+    /// the rest of the title.
+    fn relationship<'a>(&self, title: &'a str) -> (Option<String>, &'a str) {
+        for relationship in &self.relationships {
+            if let Some(rest) = title
+                .strip_prefix(relationship.name.as_str())
+                .and_then(|rest| rest.strip_prefix(':'))
+            {
+                return (Some(relationship.name.clone()), rest.trim_start());
+            }
+        }
+        (None, title)
+    }
+
+    fn supports_renderer(&self, renderer: &str) -> bool {
+        self.renderers.iter().any(|r| r == renderer)
+    }
+}
+
+fn deserialize_heading_level<'de, D: Deserializer<'de>>(d: D) -> Result<HeadingLevel, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Number(usize),
+        Name(String),
+    }
+    let level = match Raw::deserialize(d)? {
+        Raw::Number(n) => n.to_string(),
+        Raw::Name(name) => name,
+    };
+    level
+        .strip_prefix(['h', 'H'])
+        .unwrap_or(&level)
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| HeadingLevel::try_from(n).ok())
+        .ok_or_else(|| {
+            serde::de::Error::custom(format!(
+                "invalid heading level `{level}`, expected a number from 1 to 6 or `h1` to `h6`"
+            ))
+        })
+}
+
+fn deserialize_show_count<'de, D: Deserializer<'de>>(d: D) -> Result<ShowCount, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Bool(bool),
+        Name(String),
+    }
+    match Raw::deserialize(d)? {
+        Raw::Bool(false) => Ok(ShowCount::Off),
+        Raw::Bool(true) => Ok(ShowCount::Total),
+        Raw::Name(name) if name == "detailed" => Ok(ShowCount::Detailed),
+        Raw::Name(name) => Err(serde::de::Error::custom(format!(
+            "invalid show_count `{name}`, expected `true`, `false` or `detailed`"
+        ))),
+    }
+}
+
+fn deserialize_item_format<'de, D: Deserializer<'de>>(
+    d: D,
+) -> Result<Option<ItemFormat>, D::Error> {
+    let format = String::deserialize(d)?;
+    ItemFormat::parse(&format)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn deserialize_globs<'de, D: Deserializer<'de>>(d: D) -> Result<GlobSet, D::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in Vec::<String>::deserialize(d)? {
+        builder.add(Glob::new(&pattern).map_err(serde::de::Error::custom)?);
+    }
+    builder.build().map_err(serde::de::Error::custom)
+}
+
+fn deserialize_optional_globs<'de, D: Deserializer<'de>>(
+    d: D,
+) -> Result<Option<GlobSet>, D::Error> {
+    deserialize_globs(d).map(Some)
+}
+
+/// Extract the text of the first H1 heading of a chapter.
+fn first_h1(content: &str) -> Option<String> {
+    let mut title: Option<String> = None;
+    for event in mdbook_markdown::new_cmark_parser(content, &Default::default()) {
+        match event {
+            Event::Start(Tag::Heading {
+                level: HeadingLevel::H1,
+                ..
+            }) => title = Some(String::new()),
+            Event::Text(txt) | Event::Code(txt) => {
+                if let Some(title) = &mut title {
+                    title.push_str(&txt);
+                }
+            }
+            Event::End(TagEnd::Heading(HeadingLevel::H1)) => return title,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Whether a link destination points outside of the book, e.g. `https://example.com`,
+/// `mailto:x@y.com` or `//example.com`.
+fn is_external_link(dest_url: &str) -> bool {
+    if dest_url.starts_with("//") {
+        return true;
+    }
+    // A URL scheme is a letter followed by letters, digits, `+`, `-` or `.`, and then a `:`.
+    match dest_url.split_once(':') {
+        Some((scheme, _)) => {
+            scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+        }
+        None => false,
+    }
+}
+
+/// Escape the characters of a piece of text that are special in HTML.
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Compute the anchor mdbook generates for a heading with the given text.
+fn heading_id(text: &str) -> String {
+    text.chars()
+        .filter_map(|c| {
+            if c.is_alphanumeric() || c == '_' || c == '-' {
+                Some(c.to_ascii_lowercase())
+            } else if c.is_whitespace() {
+                Some('-')
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Map the anchors of the headings of a chapter to the text of these headings.
+fn heading_titles(content: &str) -> HashMap<String, String> {
+    headings(content).into_iter().collect()
+}
+
+/// The anchors of the headings of a chapter and the text of these headings, in order.
+fn headings(content: &str) -> Vec<(String, String)> {
+    let mut titles = Vec::new();
+    let mut id_counter: HashMap<String, usize> = HashMap::new();
+    let mut current: Option<(Option<String>, String)> = None;
+    for event in mdbook_markdown::new_cmark_parser(content, &Default::default()) {
+        match event {
+            Event::Start(Tag::Heading { id, .. }) => {
+                current = Some((id.map(|id| id.to_string()), String::new()));
+            }
+            Event::Text(txt) | Event::Code(txt) => {
+                if let Some((_, title)) = &mut current {
+                    title.push_str(&txt);
+                }
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                if let Some((id, title)) = current.take() {
+                    let id = id.unwrap_or_else(|| {
+                        // Like mdbook, disambiguate repeated headings with a numeric suffix.
+                        let id = heading_id(&title);
+                        let count = id_counter.entry(id.clone()).or_default();
+                        let id = if *count == 0 {
+                            id
+                        } else {
+                            format!("{id}-{count}")
+                        };
+                        *count += 1;
+                        id
+                    });
+                    titles.push((id, title));
+                }
+            }
+            _ => {}
+        }
+    }
+    titles
+}
+
+/// A link found in a chapter, before checking whether it points to a chapter of the book.
+struct ChapterLink {
+    /// Destination of the link, as written in the chapter.
+    dest_url: String,
+    /// Normalized path of the linked file.
+    dest_path: NormalizedPathBuf,
+    /// Fragment of the link, designating a location inside the linked file.
+    fragment: Option<String>,
+    /// Text of the link. Only recorded with `link_text = "anchor"`.
+    anchor_text: String,
+    /// Text of the link as Markdown. Only recorded with `link_text = "anchor"`.
+    anchor_markdown: String,
+    /// Title of the link. Only recorded with `link_titles = true`.
+    title: String,
+    /// Relationship of the link, from a `name:` prefix of its title.
+    relationship: Option<String>,
+    /// Anchor of the last heading before the link. Only recorded with `anchor_to_source = true`.
+    source_anchor: Option<String>,
+}
+
+/// Write inline events, like the text of a link, as Markdown.
+fn inline_markdown(events: &[Event<'_>]) -> String {
+    let mut markdown = String::new();
+    let _ = pulldown_cmark_to_cmark::cmark(events.iter(), &mut markdown);
+    markdown
+}
+
+/// Parse the inline Markdown written by [`inline_markdown`] back into events.
+fn inline_events(markdown: &str) -> Vec<Event<'static>> {
+    mdbook_markdown::new_cmark_parser(markdown, &Default::default())
+        .filter(|event| {
+            !matches!(
+                event,
+                Event::Start(Tag::Paragraph) | Event::End(TagEnd::Paragraph)
+            )
+        })
+        .map(Event::into_static)
+        .collect()
+}
+
+/// Find the wikilinks in a piece of text, i.e. `[[target]]` or `[[target|label]]`, and return
+/// them as `(target, label)` pairs.
+fn wikilinks(text: &str) -> Vec<(&str, &str)> {
+    let mut links = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("[[") {
+        rest = &rest[start + 2..];
+        let Some(end) = rest.find("]]") else {
+            break;
+        };
+        let inner = &rest[..end];
+        if inner.is_empty() || inner.contains(['[', ']', '\n']) {
+            continue;
+        }
+        let (target, label) = inner.split_once('|').unwrap_or((inner, inner));
+        links.push((target.trim(), label.trim()));
+        rest = &rest[end + 2..];
+    }
+    links
+}
+
+/// Find the destinations of the `<a href="...">` tags in a piece of HTML.
+fn html_hrefs(html: &str) -> Vec<&str> {
+    let lowercase = html.to_ascii_lowercase();
+    let mut hrefs = Vec::new();
+    for (start, _) in lowercase.match_indices("<a") {
+        let tag = &html[start + 2..];
+        let tag = &tag[..tag.find('>').unwrap_or(tag.len())];
+        if tag.starts_with(|c: char| c.is_ascii_whitespace())
+            && let Some(href) = html_attribute(tag, "href")
+        {
+            hrefs.push(href);
+        }
+    }
+    hrefs
+}
+
+/// Find the value of an attribute among the attributes of an HTML tag.
+fn html_attribute<'a>(attrs: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = attrs.trim_start();
+    while !rest.is_empty() {
+        let name_end = rest
+            .find(|c: char| c.is_ascii_whitespace() || c == '=')
+            .unwrap_or(rest.len());
+        let attr_name = &rest[..name_end];
+        rest = rest[name_end..].trim_start();
+        let mut value = None;
+        if let Some(after_eq) = rest.strip_prefix('=') {
+            let after_eq = after_eq.trim_start();
+            let (val, after) = match after_eq.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let quoted = &after_eq[1..];
+                    let end = quoted.find(quote).unwrap_or(quoted.len());
+                    (&quoted[..end], quoted.get(end + 1..).unwrap_or(""))
+                }
+                _ => after_eq.split_at(
+                    after_eq
+                        .find(|c: char| c.is_ascii_whitespace())
+                        .unwrap_or(after_eq.len()),
+                ),
+            };
+            value = Some(val);
+            rest = after.trim_start();
+        }
+        if attr_name.eq_ignore_ascii_case(name) {
+            return value;
+        }
+    }
+    None
+}
+
+/// Decode the `%XX` escapes of a URL path. Invalid escapes are kept as is, and the path is
+/// returned unchanged if decoding doesn't give valid UTF-8.
+fn percent_decode(path: &str) -> Cow<'_, str> {
+    if !path.contains('%') {
+        return Cow::Borrowed(path);
+    }
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) if bytes[i] == b'%' => {
+                decoded.push(byte);
+                i += 3;
+            }
+            _ => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    match String::from_utf8(decoded) {
+        Ok(decoded) => Cow::Owned(decoded),
+        Err(_) => Cow::Borrowed(path),
+    }
+}
+
+/// Resolve the destination of a link found in the chapter at `path`. Returns `None` for links
+/// that can't point to another chapter, and for links to an anchor of the same chapter unless
+/// `same_page_links` is set.
+fn resolve_link(
+    dest_url: &str,
+    path: &NormalizedPathBuf,
+    config: &Config,
+) -> Result<Option<ChapterLink>, Error> {
+    if dest_url.is_empty() {
+        // An empty destination doesn't point anywhere, not even to the chapter itself.
+        if config.warn_empty_links {
+            eprintln!(
+                "Warning: {} contains a link with an empty destination",
+                path.relative_to_root().display()
+            );
+        }
+        trace!(
+            "{}: ignoring link with an empty destination",
+            path.display()
+        );
+        return Ok(None);
+    }
+    if is_external_link(dest_url) {
+        trace!("{}: ignoring external link `{dest_url}`", path.display());
+        return Ok(None);
+    }
+    // The fragment designates a location inside the target chapter.
+    let (dest_path, fragment) = match dest_url.split_once('#') {
+        Some((path, fragment)) => (path, Some(fragment)),
+        None => (dest_url, None),
+    };
+    // The query string doesn't change which chapter the link points to.
+    let dest_path = dest_path
+        .split_once('?')
+        .map_or(dest_path, |(path, _)| path);
+    let dest_path = if dest_path.is_empty() {
+        // A link to an anchor of the same chapter, which we don't resolve against the directory.
+        if !config.same_page_links {
+            trace!("{}: ignoring same-page link `{dest_url}`", path.display());
+            return Ok(None);
+        }
+        path.clone()
+    } else {
+        // Accept Windows-style separators.
+        let dest_path = percent_decode(dest_path).replace('\\', "/");
+        let dest_path = match dest_path.strip_prefix('/') {
+            Some(from_root) if config.root_relative => PathBuf::from(from_root),
+            _ => path.dir().join(PathBuf::from(dest_path)),
+        };
+        // Relative paths that go above the source directory can't be normalized.
+        let normalized = dest_path.normalize_path().ok();
+        if config.warn_escapes
+            && let Some(warning) = escape_warning(path, dest_url, normalized.as_ref())
+        {
+            eprintln!("{warning}");
+        }
+        let Some(dest_path) = normalized else {
+            trace!(
+                "{}: ignoring link `{dest_url}` outside of the book",
+                path.display()
+            );
+            return Ok(None);
+        };
+        dest_path
+    };
+    Ok(Some(ChapterLink {
+        dest_url: dest_url.to_owned(),
+        dest_path,
+        fragment: fragment.filter(|f| !f.is_empty()).map(str::to_owned),
+        anchor_text: String::new(),
+        anchor_markdown: String::new(),
+        title: String::new(),
+        relationship: None,
+        source_anchor: None,
+    }))
+}
+
+/// The warning about a link from the chapter at `path` to `dest_url`, normalized to `dest`, if it
+/// points outside of the book's source directory: above it, like `../../notes.md`, when it can't
+/// be normalized, or to an absolute path.
+fn escape_warning(
+    path: &NormalizedPathBuf,
+    dest_url: &str,
+    dest: Option<&NormalizedPathBuf>,
+) -> Option<String> {
+    dest.is_none_or(|dest| dest.is_absolute()).then(|| {
+        format!(
+            "Warning: {} links to `{dest_url}`, which is outside of the book's source directory",
+            path.relative_to_root().display()
+        )
+    })
+}
+
+/// Collect the internal links of the chapter at `path`.
+fn chapter_links(
+    content: &str,
+    path: &NormalizedPathBuf,
+    config: &Config,
+) -> Result<Vec<ChapterLink>, Error> {
+    let mut links: Vec<ChapterLink> = Vec::new();
+    // Whether we're inside of the last link of `links`.
+    let mut in_link = false;
+    // Whether we're inside of a link we're ignoring.
+    let mut in_ignored_link = false;
+    // Whether we're inside of a code block, whose text isn't searched for wikilinks.
+    let mut in_code_block = false;
+    // The formatted text of the last link of `links`, with `link_text = "anchor"`.
+    let mut anchor_events = Vec::new();
+    // The anchors of the headings of the chapter, and how many links come before each of them.
+    // Only parsed with `anchor_to_source`, since it takes a second pass over the chapter.
+    let mut heading_anchors = config
+        .anchor_to_source
+        .then(|| headings(content).into_iter().map(|(id, _)| id));
+    let mut sections = Vec::new();
+    // Loop over the internal links found in the chapter. The parser resolves reference,
+    // collapsed and shortcut links, so `dest_url` is always the final destination.
+    let parser = mdbook_markdown::new_cmark_parser(content, &Default::default());
+    for event in TextMergeStream::new(parser) {
+        match event {
+            Event::Start(Tag::Heading { .. }) if config.anchor_to_source => {
+                if let Some(id) = heading_anchors.as_mut().and_then(Iterator::next) {
+                    sections.push((links.len(), id));
+                }
+            }
+            Event::Text(ref txt) | Event::Code(ref txt)
+                if in_link && config.link_text == LinkText::Anchor =>
+            {
+                links.last_mut().unwrap().anchor_text += txt;
+                anchor_events.push(event.clone());
+            }
+            Event::Start(Tag::Emphasis | Tag::Strong | Tag::Strikethrough)
+            | Event::End(TagEnd::Emphasis | TagEnd::Strong | TagEnd::Strikethrough)
+                if in_link && config.link_text == LinkText::Anchor =>
+            {
+                anchor_events.push(event);
+            }
+            Event::Start(Tag::CodeBlock(_)) => in_code_block = true,
+            Event::End(TagEnd::CodeBlock) => in_code_block = false,
+            Event::Text(txt)
+                if config.wikilinks && !in_link && !in_ignored_link && !in_code_block =>
+            {
+                for (target, label) in wikilinks(&txt) {
+                    // Wikilinks usually omit the extension of the target.
+                    let ext = config.source_extensions().next().unwrap_or("md");
+                    let dest_url = match target.split_once('#') {
+                        Some((page, _))
+                            if page.is_empty() || Path::new(page).extension().is_some() =>
+                        {
+                            target.to_owned()
+                        }
+                        Some((page, fragment)) => format!("{page}.{ext}#{fragment}"),
+                        None if Path::new(target).extension().is_some() => target.to_owned(),
+                        None => format!("{target}.{ext}"),
+                    };
+                    if let Some(mut link) = resolve_link(&dest_url, path, config)? {
+                        if config.link_text == LinkText::Anchor {
+                            link.anchor_text = label.to_owned();
+                        }
+                        links.push(link);
+                    }
+                }
+            }
+            Event::Html(html) | Event::InlineHtml(html) if config.html_links => {
+                for href in html_hrefs(&html) {
+                    links.extend(resolve_link(href, path, config)?);
+                }
+            }
+            Event::End(TagEnd::Link) => {
+                if in_link && !anchor_events.is_empty() {
+                    links.last_mut().unwrap().anchor_markdown = inline_markdown(&anchor_events);
+                    anchor_events.clear();
+                }
+                in_link = false;
+                in_ignored_link = false;
+            }
+            // Images embedded from other chapters aren't backlinks unless `count_images` is set.
+            // Their alt text is their anchor text. Images inside of links are part of the link.
+            Event::Start(Tag::Image {
+                dest_url, title, ..
+            }) if config.count_images && !in_link && !in_ignored_link => {
+                match resolve_link(&dest_url, path, config)? {
+                    // Actual pictures aren't broken links.
+                    Some(mut link) if config.is_source(link.dest_path.as_ref()) => {
+                        if config.link_titles {
+                            link.title = title.to_string();
+                        }
+                        links.push(link);
+                        in_link = true;
+                    }
+                    _ => in_ignored_link = true,
+                }
+            }
+            Event::End(TagEnd::Image) if config.count_images => {
+                if in_link && !anchor_events.is_empty() {
+                    links.last_mut().unwrap().anchor_markdown = inline_markdown(&anchor_events);
+                    anchor_events.clear();
+                }
+                in_link = false;
+                in_ignored_link = false;
+            }
+            Event::Start(Tag::Link { link_type, .. }) if !config.counts_link_type(link_type) => {
+                in_ignored_link = true;
+            }
+            Event::Start(Tag::Link {
+                dest_url, title, ..
+            }) => match resolve_link(&dest_url, path, config)? {
+                Some(mut link) => {
+                    let (relationship, title) = config.relationship(&title);
+                    if config.link_titles {
+                        link.title = title.to_owned();
+                    }
+                    link.relationship = relationship;
+                    links.push(link);
+                    in_link = true;
+                }
+                None => in_ignored_link = true,
+            },
+            _ => {}
+        }
+    }
+    for (i, link) in links.iter_mut().enumerate() {
+        link.source_anchor = sections
+            .iter()
+            .take_while(|(start, _)| *start <= i)
+            .last()
+            .map(|(_, id)| id.clone());
+    }
+    Ok(links)
+}
+
+/// How deep we follow `{{#include}}` directives in included files, like mdbook does.
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+/// Replace the `{{#include}}` directives of `content` by the files they include, relative to
+/// `dir`. Line ranges and anchors are ignored: we include the whole file, which can only find more
+/// links than mdbook would render. Directives whose file can't be read are kept as is.
+fn inline_includes(content: &str, dir: &Path, depth: usize) -> String {
+    let mut result = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.find("{{#include")
+        && let Some(len) = rest[start..].find("}}")
+    {
+        let directive = &rest[start..start + len + 2];
+        let arg = directive["{{#include".len()..directive.len() - 2].trim();
+        let file = arg.split_once(':').map_or(arg, |(file, _)| file);
+        let path = dir.join(file);
+        result += &rest[..start];
+        match std::fs::read_to_string(&path) {
+            Ok(included) if depth > 0 && !file.is_empty() => {
+                let included_dir = path.parent().unwrap_or(dir);
+                result += &inline_includes(&included, included_dir, depth - 1);
+            }
+            _ => result += directive,
+        }
+        rest = &rest[start + len + 2..];
+    }
+    result += rest;
+    result
+}
+
+/// The names of the draft chapters that contain links. Drafts have no path, so their links can't
+/// be resolved and we ignore them.
+fn drafts_with_links(book: &Book) -> Vec<&str> {
+    book.iter()
+        .filter_map(|item| match item {
+            BookItem::Chapter(ch) if ch.is_draft_chapter() => Some(ch),
+            _ => None,
+        })
+        .filter(|ch| {
+            mdbook_markdown::new_cmark_parser(&ch.content, &Default::default())
+                .any(|event| matches!(event, Event::Start(Tag::Link { .. })))
+        })
+        .map(|ch| ch.name.as_str())
+        .collect()
+}
+
+/// The links between the chapters of a book, keyed by normalized chapter path (see
+/// [`Config::path_key`]).
+#[derive(Debug, Default)]
+struct LinkGraph {
+    /// The backlinks of each chapter.
+    backlinks: HashMap<NormalizedPathBuf, Vec<Backlink>>,
+    /// The chapters each chapter links to.
+    forward: HashMap<NormalizedPathBuf, Vec<Rc<ChapterInfo>>>,
+    /// The information about each chapter.
+    chapters: HashMap<NormalizedPathBuf, Rc<ChapterInfo>>,
+    /// The normalized path of each chapter, keyed by its `source_path`, so that we normalize it
+    /// only once.
+    paths: HashMap<PathBuf, NormalizedPathBuf>,
+}
+
+impl LinkGraph {
+    fn collect(book: &Book, config: &Config) -> Result<Self, Error> {
+        // Map each chapters source_path to its backlinks.
+        let mut backlinks_map: HashMap<NormalizedPathBuf, Vec<Backlink>> = HashMap::new();
+        // Map each chapters source_path to the chapters it links to.
+        let mut forward_map: HashMap<NormalizedPathBuf, Vec<Rc<ChapterInfo>>> = HashMap::new();
+        // Map each chapters source_path to its information.
+        let mut chapters: HashMap<NormalizedPathBuf, Rc<ChapterInfo>> = HashMap::new();
+        // Map each chapters source_path to its normalized version.
+        let mut paths = HashMap::new();
+
+        // Add entries for the book chapters (so that we don't accumulate links that point outside
+        // the book).
+        // The title of the part we're in.
+        let mut part = None;
+        for item in book.iter() {
+            if let BookItem::PartTitle(title) = item {
+                part = Some(title);
+            }
+            if let BookItem::Chapter(ch) = item
+                && let Some(path) = &ch.source_path
+            {
+                let source_path = path;
+                let path = source_path.normalize_path().with_context(|| {
+                    format!(
+                        "failed to normalize the path of chapter `{}`",
+                        source_path.display()
+                    )
+                })?;
+                paths.insert(source_path.clone(), path.clone());
+                let key = config.path_key(&path);
+                // Two chapters with the same file would get each other's backlinks.
+                if let Some(other) = chapters.get(&key) {
+                    bail!(
+                        "chapters `{}` and `{}` have the same source path `{}`",
+                        other.name,
+                        ch.name,
+                        path.relative_to_root().display()
+                    );
+                }
+                backlinks_map.insert(key.clone(), Vec::new());
+                forward_map.insert(key.clone(), Vec::new());
+                let title = match config.title_source {
+                    TitleSource::Heading => first_h1(&ch.content),
+                    TitleSource::Name => None,
+                };
+                let info = ChapterInfo {
+                    number: ch.number.clone().map(|n| Vec::clone(&n)),
+                    name: ch.name.clone(),
+                    path,
+                    title: title.unwrap_or_else(|| ch.name.clone()),
+                    ancestors: part.into_iter().chain(&ch.parent_names).cloned().collect(),
+                };
+                chapters.insert(key, Rc::new(info));
+            }
+        }
+
+        if config.warn_drafts {
+            for name in drafts_with_links(book) {
+                eprintln!("Warning: ignoring the links of draft chapter `{name}`");
+            }
+        }
+
+        // Links whose target isn't a chapter of the book, as (source chapter, link destination).
+        // This includes links to draft chapters, since they have no file to link to.
+        let mut broken_links = Vec::new();
+
+        // Parse the chapters in parallel. We then merge the results sequentially, which preserves
+        // the order in which the links appear in the book.
+        let book_chapters = book
+            .iter()
+            .filter_map(|item| match item {
+                BookItem::Chapter(ch) => Some((ch, ch.source_path.as_ref()?)),
+                _ => None,
+            })
+            .collect_vec();
+        let links_per_chapter = book_chapters
+            .par_iter()
+            .map(|(ch, source_path)| {
+                let path = &paths[*source_path];
+                let content = if config.resolve_includes {
+                    let dir = config.src_dir.join(path.dir());
+                    Cow::Owned(inline_includes(&ch.content, &dir, MAX_INCLUDE_DEPTH))
+                } else {
+                    Cow::Borrowed(&ch.content)
+                };
+                let links = chapter_links(&content, path, config)?;
+                Ok((config.path_key(path), links))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        // Populate the map.
+        for ((_, source_path), (path, links)) in book_chapters.iter().zip(links_per_chapter) {
+            trace!(
+                "{}: found {} internal link(s)",
+                source_path.display(),
+                links.len()
+            );
+            if !config.in_scope(&chapters[&path].path) {
+                trace!(
+                    "{}: ignoring its links, since it doesn't match `include`",
+                    source_path.display()
+                );
+                continue;
+            }
+            for link in links {
+                let mut dest = config.path_key(&link.dest_path);
+                // Links to a directory point to its index chapter. With `resolve_extensionless`,
+                // a chapter the link names without its extension comes first, so that a
+                // directory of the same name doesn't take its links.
+                if !backlinks_map.contains_key(&dest) && dest.extension().is_none() {
+                    // A link ending with a `/`, `.` or `..` names a directory, not a chapter.
+                    let url_path = link.dest_url.split(['#', '?']).next().unwrap_or_default();
+                    let names_file = !matches!(url_path.rsplit('/').next(), Some("" | "." | ".."));
+                    let files = config
+                        .source_extensions()
+                        .filter(|_| config.resolve_extensionless && names_file)
+                        .map(|ext| dest.with_extension(ext));
+                    let indexes = config.index_names.iter().flat_map(|name| {
+                        config
+                            .source_extensions()
+                            .map(|ext| dest.join(name.with_extension(ext)))
+                    });
+                    for candidate in files.chain(indexes).collect_vec() {
+                        let candidate = config.path_key(&candidate.normalize_path()?);
+                        if backlinks_map.contains_key(&candidate) {
+                            dest = candidate;
+                            break;
+                        }
+                    }
+                }
+                trace!(
+                    "{}: link to `{}` resolves to `{}`",
+                    source_path.display(),
+                    link.dest_url,
+                    dest.display()
+                );
+                if dest == path && !config.include_self {
+                    trace!("{}: ignoring link to itself", source_path.display());
+                    continue;
+                }
+                if let Some(backlinks) = backlinks_map.get_mut(&dest) {
+                    if !config.in_scope(&chapters[&dest].path) {
+                        trace!(
+                            "{}: ignoring link to `{}`, which doesn't match `include`",
+                            source_path.display(),
+                            dest.display()
+                        );
+                        continue;
+                    }
+                    let part = |info: &ChapterInfo| info.number.as_ref()?.first().copied();
+                    if config.scope == Scope::Part
+                        && (part(&chapters[&path]).is_none()
+                            || part(&chapters[&path]) != part(&chapters[&dest]))
+                    {
+                        trace!(
+                            "{}: ignoring link to `{}`, which is in another part",
+                            source_path.display(),
+                            dest.display()
+                        );
+                        continue;
+                    }
+                    backlinks.push(Backlink {
+                        source: chapters[&path].clone(),
+                        fragment: link.fragment,
+                        anchor_text: link.anchor_text,
+                        anchor_markdown: link.anchor_markdown,
+                        title: link.title,
+                        relationship: link.relationship,
+                        source_anchor: link.source_anchor,
+                    });
+                    let dest_chapter = chapters[&dest].clone();
+                    forward_map.get_mut(&path).unwrap().push(dest_chapter);
+                } else {
+                    trace!(
+                        "{}: `{}` is not a chapter of the book",
+                        source_path.display(),
+                        dest.display()
+                    );
+                    broken_links.push((*source_path, link.dest_url));
+                }
+            }
+        }
+
+        if config.check_broken || config.fail_on_broken {
+            for (path, dest_url) in &broken_links {
+                eprintln!(
+                    "Warning: {} links to `{dest_url}`, which is not a chapter of the book",
+                    path.display()
+                );
+            }
+            if config.fail_on_broken && !broken_links.is_empty() {
+                bail!("found {} broken internal link(s)", broken_links.len());
+            }
+        }
+
+        let graph = LinkGraph {
+            backlinks: backlinks_map,
+            forward: forward_map,
+            chapters,
+            paths,
+        };
+        Ok(graph)
+    }
+
+    /// The normalized path of the chapter with the given `source_path`.
+    fn chapter_path(&self, source_path: &Path) -> Result<NormalizedPathBuf, Error> {
+        match self.paths.get(source_path) {
+            Some(path) => Ok(path.clone()),
+            None => source_path.normalize_path().with_context(|| {
+                format!(
+                    "failed to normalize the path of chapter `{}`",
+                    source_path.display()
+                )
+            }),
+        }
+    }
+
+    /// The chapters that no other chapter links to, except those listed in `orphan_ignore`.
+    fn orphans(&self, config: &Config) -> Result<Vec<&Path>, Error> {
+        let ignored = config
+            .orphan_ignore
+            .iter()
+            .map(|path| Ok(config.path_key(&path.normalize_path()?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        Ok(self
+            .backlinks
+            .iter()
+            .filter(|(path, backlinks)| backlinks.is_empty() && !ignored.contains(path))
+            .map(|(path, _)| self.chapters[path].path.relative_to_root())
+            .sorted()
+            .collect())
+    }
+
+    /// The chapters with the most backlinks, with their number of backlinks as shown to readers.
+    /// Ties are broken by chapter name.
+    fn hubs(&self, limit: usize, dedup: Dedup) -> Vec<(&ChapterInfo, usize)> {
+        self.backlinks
+            .iter()
+            .map(|(path, backlinks)| {
+                let count = sorted_backlinks(backlinks, SortOrder::Number, dedup).len();
+                (&*self.chapters[path], count)
+            })
+            .sorted_by(|(a, a_count), (b, b_count)| {
+                b_count.cmp(a_count).then_with(|| a.name.cmp(&b.name))
+            })
+            .take(limit)
+            .collect()
+    }
+
+    /// The chapters that link to the most of the same chapters as the given one, as measured by
+    /// the Jaccard index of their sets of forward links, best first. Ties are broken by path.
+    fn related(&self, key: &NormalizedPathBuf, count: usize) -> Vec<&Rc<ChapterInfo>> {
+        let targets = |key: &NormalizedPathBuf| -> HashSet<&NormalizedPathBuf> {
+            self.forward[key].iter().map(|info| &info.path).collect()
+        };
+        let own = targets(key);
+        if own.is_empty() {
+            return Vec::new();
+        }
+        self.forward
+            .keys()
+            .filter(|other| *other != key)
+            .filter_map(|other| {
+                let theirs = targets(other);
+                let shared = own.intersection(&theirs).count();
+                let score = shared as f64 / own.union(&theirs).count() as f64;
+                (shared != 0).then(|| (score, &self.chapters[other]))
+            })
+            .sorted_by(|(a_score, a), (b_score, b)| {
+                b_score.total_cmp(a_score).then_with(|| a.path.cmp(&b.path))
+            })
+            .take(count)
+            .map(|(_, info)| info)
+            .collect()
+    }
+
+    /// The title of the top-level chapter with the given number.
+    fn part_title(&self, part: u32) -> Option<&str> {
+        self.chapters
+            .values()
+            .find(|info| info.number.as_deref() == Some(&[part]))
+            .map(|info| info.title.as_str())
+    }
+
+    /// Render the graph in the Graphviz DOT format, with one node per chapter and one edge per
+    /// pair of linked chapters.
+    fn to_dot(&self) -> String {
+        let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+        let mut dot = String::from("digraph backlinks {\n");
+        for info in self.chapters.values().sorted_by_key(|info| &info.path) {
+            let id = quote(&info.path.to_string_lossy());
+            dot += &format!("    {id} [label={}];\n", quote(&info.name));
+        }
+        for (path, links) in self.forward.iter().sorted() {
+            let id = quote(&self.chapters[path].path.to_string_lossy());
+            for dest in links.iter().map(|link| &link.path).sorted().dedup() {
+                dot += &format!("    {id} -> {};\n", quote(&dest.to_string_lossy()));
+            }
+        }
+        dot += "}\n";
+        dot
+    }
+
+    /// Render the backlinks of every chapter as a JSON object keyed by chapter path.
+    fn to_json(&self) -> Result<String, Error> {
+        #[derive(Serialize, PartialEq, Eq, PartialOrd, Ord)]
+        struct JsonBacklink<'a> {
+            name: &'a str,
+            path: &'a Path,
+            number: Option<&'a [u32]>,
+        }
+        let map: BTreeMap<_, Vec<_>> = self
+            .backlinks
+            .iter()
+            .map(|(path, backlinks)| {
+                let backlinks = backlinks
+                    .iter()
+                    .map(|backlink| JsonBacklink {
+                        name: &backlink.source.name,
+                        path: backlink.source.path.relative_to_root(),
+                        number: backlink.source.number.as_deref(),
+                    })
+                    .sorted()
+                    .dedup()
+                    .collect();
+                (self.chapters[path].path.relative_to_root(), backlinks)
+            })
+            .collect();
+        serde_json::to_string_pretty(&map)
+            .context("failed to serialize the backlinks, JSON paths must be valid UTF-8")
+    }
+
+    /// The [`LinkCounts`] of every chapter, in JSON.
+    fn counts_to_json(&self) -> Result<String, Error> {
+        let map: BTreeMap<_, _> = self
+            .backlinks
+            .iter()
+            .map(|(path, backlinks)| {
+                let path = self.chapters[path].path.relative_to_root();
+                (path, LinkCounts::of(backlinks))
+            })
+            .collect();
+        serde_json::to_string_pretty(&map)
+            .context("failed to serialize the link counts, JSON paths must be valid UTF-8")
+    }
+}
+
+/// How many links point to a chapter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+struct LinkCounts {
+    /// The number of links, before deduplication.
+    links: usize,
+    /// The number of distinct chapters the links come from.
+    pages: usize,
+}
+
+impl LinkCounts {
+    fn of(backlinks: &[Backlink]) -> Self {
+        LinkCounts {
+            links: backlinks.len(),
+            pages: backlinks.iter().map(|b| &b.source.path).unique().count(),
+        }
+    }
+}
+
+/// Marks the start of a block inserted by this preprocessor.
+const BLOCK_START: &str = "<!-- backlinks -->";
+/// Marks the start of a block inserted by this preprocessor in place of a [`PLACEHOLDER`].
+const PLACEHOLDER_BLOCK_START: &str = "<!-- backlinks placeholder -->";
+/// Marks the end of a block inserted by this preprocessor.
+const BLOCK_END: &str = "<!-- /backlinks -->";
+/// Written by authors where they want the backlinks block to go.
+const PLACEHOLDER: &str = "{{#backlinks}}";
+/// Written by authors in chapters that shouldn't get a backlinks block.
+const OPT_OUT: &str = "<!-- no-backlinks -->";
+
+/// Whether the chapter opted out of getting a backlinks block, with either an [`OPT_OUT`] comment
+/// or a `backlinks: false` key in its frontmatter.
+fn opted_out(content: &str) -> bool {
+    if content.contains(OPT_OUT) {
+        return true;
+    }
+    let mut lines = content.lines();
+    if lines.next().map(str::trim_end) != Some("---") {
+        return false;
+    }
+    lines
+        .take_while(|line| line.trim_end() != "---")
+        .filter_map(|line| line.split_once(':'))
+        .any(|(key, value)| key.trim() == "backlinks" && value.trim() == "false")
+}
+
+/// The offset of the whitespace and link reference definitions that end `content`, which don't
+/// produce any event.
+fn trailing_definitions(content: &str) -> usize {
+    mdbook_markdown::new_cmark_parser(content, &Default::default())
+        .into_offset_iter()
+        .map(|(_, range)| range.end)
+        .max()
+        .unwrap_or(0)
+}
+
+/// The byte ranges of the [`PLACEHOLDER`]s of `content`. Placeholders in code spans and code
+/// blocks are left out, since they are there to be shown rather than replaced.
+fn placeholder_ranges(content: &str) -> Vec<Range<usize>> {
+    let code = mdbook_markdown::new_cmark_parser(content, &Default::default())
+        .into_offset_iter()
+        .filter(|(event, _)| matches!(event, Event::Code(_) | Event::Start(Tag::CodeBlock(_))))
+        .map(|(_, range)| range)
+        .collect_vec();
+    content
+        .match_indices(PLACEHOLDER)
+        .map(|(start, _)| start..start + PLACEHOLDER.len())
+        .filter(|token| {
+            !code
+                .iter()
+                .any(|code| code.start <= token.start && token.end <= code.end)
+        })
+        .collect()
+}
+
+/// Replace the [`PLACEHOLDER`]s of `content` outside of code by `block`.
+fn replace_placeholders(content: &mut String, block: &str) {
+    // Replace from the end, so that the earlier ranges stay valid.
+    for range in placeholder_ranges(content).into_iter().rev() {
+        content.replace_range(range, block);
+    }
+}
+
+/// Remove the blocks inserted by previous runs of this preprocessor, so that running it twice
+/// doesn't accumulate blocks (nor count the links they contain). A block that other preprocessors
+/// added content after and before is replaced by a [`PLACEHOLDER`], so that it stays there.
+fn remove_backlinks_blocks(book: &mut Book) {
+    book.for_each_mut(|item| {
+        if let BookItem::Chapter(ch) = item {
+            while let Some(mut start) = ch.content.find(BLOCK_START)
+                && let Some(len) = ch.content[start..].find(BLOCK_END)
+            {
+                let mut end = start + len + BLOCK_END.len();
+                if !ch.content[..start].trim().is_empty()
+                    && trailing_definitions(&ch.content[end..]) != 0
+                {
+                    ch.content.replace_range(start..end, PLACEHOLDER);
+                    continue;
+                }
+                // Also remove the padding we added around the block.
+                if ch.content[..start].ends_with("\n\n") {
+                    start -= 2;
+                } else if ch.content[end..].starts_with("\n\n") {
+                    end += 2;
+                }
+                ch.content.replace_range(start..end, "");
+            }
+            // Put back the placeholders we replaced.
+            while let Some(start) = ch.content.find(PLACEHOLDER_BLOCK_START)
+                && let Some(len) = ch.content[start..].find(BLOCK_END)
+            {
+                let end = start + len + BLOCK_END.len();
+                ch.content.replace_range(start..end, PLACEHOLDER);
+            }
+        }
+    });
+}
+
+/// Format a section number as a dotted string, e.g. `2.3.1.4`. Empty numbers give an empty
+/// string.
+fn section_number(number: &[u32]) -> String {
+    number.iter().join(".")
+}
+
+/// Deduplicate backlinks and sort them in the given order.
+fn sorted_backlinks(backlinks: &[Backlink], sort: SortOrder, dedup: Dedup) -> Vec<&Backlink> {
+    let mut backlinks = match dedup {
+        Dedup::Full => backlinks.iter().unique().collect_vec(),
+        Dedup::Anchor => backlinks
+            .iter()
+            .unique_by(|b| b.without_fragment())
+            .collect_vec(),
+        Dedup::Path => backlinks.iter().unique_by(|b| &b.source.path).collect_vec(),
+        Dedup::None => backlinks.iter().collect_vec(),
+    };
+    match sort {
+        SortOrder::Number => backlinks.sort(),
+        SortOrder::Name => backlinks.sort_by_key(|b| (&b.source.title, *b)),
+        SortOrder::Path => backlinks.sort_by_key(|b| (&b.source.path, *b)),
+        SortOrder::Document => {}
+    }
+    backlinks
+}
+
+/// Add the backlinks block to each chapter.
+fn add_backlinks(mut book: Book, graph: &LinkGraph, config: &Config) -> Result<Book, Error> {
+    if !config.supports_renderer(&config.renderer) {
+        book.for_each_mut(|item| {
+            if let BookItem::Chapter(ch) = item {
+                replace_placeholders(&mut ch.content, "");
+            }
+        });
+        return Ok(book);
+    }
+    let mut result = Ok(());
+    book.for_each_mut(|item| {
+        if let BookItem::Chapter(ch) = item
+            && result.is_ok()
+        {
+            result = add_backlinks_to_chapter(ch, graph, config);
+        }
+    });
+    result.map(|()| book)
+}
+
+/// Add the backlinks block to a chapter, in place of its placeholders if it has any.
+fn add_backlinks_to_chapter(
+    ch: &mut Chapter,
+    graph: &LinkGraph,
+    config: &Config,
+) -> Result<(), Error> {
+    // Chapters that get no block don't keep their placeholders either.
+    let Some(source_path) = &ch.source_path else {
+        replace_placeholders(&mut ch.content, "");
+        return Ok(());
+    };
+    let path = graph.chapter_path(source_path)?;
+    if opted_out(&ch.content)
+        || !config.in_scope(&path)
+        || config.exclude.is_match(path.relative_to_root())
+    {
+        replace_placeholders(&mut ch.content, "");
+        return Ok(());
+    }
+    let mut rendered = render_backlinks(&ch.name, &ch.content, source_path, graph, config)?;
+    if config.embed_metadata
+        && let Some(rendered) = &mut rendered
+    {
+        rendered.insert_str(
+            0,
+            &format!("{}\n\n", metadata_comment(&path, graph, config)?),
+        );
+    }
+    if !placeholder_ranges(&ch.content).is_empty() {
+        // Keep a block even if empty, so that the placeholder can be restored.
+        let block = match rendered {
+            Some(rendered) => format!("{PLACEHOLDER_BLOCK_START}\n\n{rendered}\n\n{BLOCK_END}"),
+            None => format!("{PLACEHOLDER_BLOCK_START}\n{BLOCK_END}"),
+        };
+        replace_placeholders(&mut ch.content, &block);
+        return Ok(());
+    }
+    let Some(rendered) = rendered else {
+        return Ok(());
+    };
+    if config.placeholder_only {
+        return Ok(());
+    }
+    let mut block = format!("{BLOCK_START}\n\n{rendered}\n\n{BLOCK_END}");
+    match config.position {
+        Position::Top => {
+            // Keep the chapter content from being parsed as part of the block.
+            block += "\n\n";
+            ch.content.insert_str(0, &block);
+        }
+        Position::Bottom => {
+            // Keep the link reference definitions at the end of the chapter, if any.
+            let end = trailing_definitions(&ch.content);
+            let end = if ch.content[end..].trim().is_empty() {
+                ch.content.len()
+            } else {
+                ch.content[..end].trim_end().len()
+            };
+            // Avoid the ruler being parsed as a heading underline, or the block being parsed as
+            // part of the last paragraph.
+            ch.content.insert_str(end, &format!("\n\n{block}"));
+        }
+    }
+    Ok(())
+}
+
+/// Add the `index_chapter`, if any, to the end of the book.
+fn add_index_chapter(book: &mut Book, graph: &LinkGraph, config: &Config) -> Result<(), Error> {
+    let Some(index_path) = &config.index_chapter else {
+        return Ok(());
+    };
+    if !config.supports_renderer(&config.renderer) {
+        return Ok(());
+    }
+    let index_key = index_path
+        .normalize_path()
+        .with_context(|| format!("invalid `index_chapter` `{}`", index_path.display()))?;
+    let index_dir = index_key.dir().to_owned();
+    let index_key = config.path_key(&index_key);
+    let list_start = (config.list_style == ListStyle::Numbered).then_some(1);
+    let mut builder = MarkdownBuilder::default();
+    builder.heading(HeadingLevel::H1, None, |builder| {
+        builder.text(config.index_title.clone());
+    });
+    for item in book.iter() {
+        let BookItem::Chapter(ch) = item else {
+            continue;
+        };
+        let Some(source_path) = &ch.source_path else {
+            continue;
+        };
+        let path = graph.chapter_path(source_path)?;
+        if config.path_key(&path) == index_key {
+            bail!(
+                "`index_chapter` `{}` is already a chapter of the book",
+                index_path.display()
+            );
+        }
+        let backlinks = &graph.backlinks[&config.path_key(&path)];
+        if backlinks.is_empty()
+            || !config.in_scope(&path)
+            || config.exclude.is_match(path.relative_to_root())
+        {
+            continue;
+        }
+        let url = |path: &NormalizedPathBuf| -> Result<String, Error> {
+            let url = relative_url(path.as_ref(), &index_dir, index_path.display())?;
+            Ok(config.link_url(url))
+        };
+        let chapter_url = url(&path)?;
+        builder.heading(HeadingLevel::H2, None, |builder| {
+            builder.simple_link(chapter_url, |builder| {
+                builder.text(ch.name.clone());
+            });
+        });
+        let backlinks = sorted_backlinks(backlinks, config.sort, config.dedup)
+            .into_iter()
+            .map(|backlink| Ok((backlink, url(&backlink.source.path)?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        builder.tag(Tag::List(list_start), |builder| {
+            for (backlink, url) in backlinks {
+                builder.tag(Tag::Item, |builder| {
+                    builder.simple_link(url, |builder| {
+                        builder.text(backlink.source.title.clone());
+                    });
+                });
+            }
+        });
+    }
+    let mut content = String::new();
+    builder.write_to_string(&mut content);
+    let mut index = Chapter::new(&config.index_title, content, index_path, vec![]);
+    // It has no file in the source directory.
+    index.source_path = None;
+    book.push_item(index);
+    Ok(())
+}
+
+/// A hidden comment with the urls of the chapters that link to the chapter at `path`, as a JSON
+/// array, e.g. `<!-- backlinks: ["../a/ch1.md"] -->`.
+fn metadata_comment(
+    path: &NormalizedPathBuf,
+    graph: &LinkGraph,
+    config: &Config,
+) -> Result<String, Error> {
+    let urls = graph.backlinks[&config.path_key(path)]
+        .iter()
+        .map(|backlink| &backlink.source.path)
+        .unique()
+        .map(|source| relative_url(source.as_ref(), path.dir(), path.display()))
+        .collect::<Result<Vec<_>, Error>>()?;
+    // A `-->` in a url would end the comment early.
+    let json = serde_json::to_string(&urls)?.replace("-->", "--\\u003e");
+    Ok(format!("<!-- backlinks: {json} -->"))
+}
+
+/// Render the backlinks (and forward links) of a chapter with a user-provided template.
+fn render_template(
+    template: &str,
+    name: &str,
+    path: &NormalizedPathBuf,
+    backlinks: &[(&Backlink, String)],
+    forward_links: &[(&Rc<ChapterInfo>, String)],
+    related: &[(&Rc<ChapterInfo>, String)],
+) -> Result<String, Error> {
+    #[derive(Serialize)]
+    struct TemplateChapter<'a> {
+        name: &'a str,
+        path: &'a Path,
+    }
+    #[derive(Serialize)]
+    struct TemplateLink<'a> {
+        name: &'a str,
+        url: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        number: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        relationship: Option<&'a str>,
+    }
+    #[derive(Serialize)]
+    struct TemplateData<'a> {
+        chapter: TemplateChapter<'a>,
+        backlinks: Vec<TemplateLink<'a>>,
+        links: Vec<TemplateLink<'a>>,
+        related: Vec<TemplateLink<'a>>,
+    }
+    fn link<'a>(info: &'a ChapterInfo, url: &'a str) -> TemplateLink<'a> {
+        TemplateLink {
+            name: &info.title,
+            url,
+            number: info
+                .number
+                .as_deref()
+                .filter(|number| !number.is_empty())
+                .map(section_number),
+            relationship: None,
+        }
+    }
+    let data = TemplateData {
+        chapter: TemplateChapter {
+            name,
+            path: path.relative_to_root(),
+        },
+        backlinks: backlinks
+            .iter()
+            .map(|(backlink, url)| TemplateLink {
+                relationship: backlink.relationship.as_deref(),
+                ..link(&backlink.source, url)
+            })
+            .collect(),
+        links: forward_links
+            .iter()
+            .map(|(info, url)| link(info, url))
+            .collect(),
+        related: related.iter().map(|(info, url)| link(info, url)).collect(),
+    };
+    Handlebars::new()
+        .render_template(template, &data)
+        .context("failed to render the backlinks template")
+}
+
+/// The url of `path` relative to the directory `dir` of `chapter`. Fails if it isn't valid UTF-8.
+fn relative_url(path: &Path, dir: &Path, chapter: impl Display) -> Result<String, Error> {
+    let diff_path = pathdiff::diff_paths(path, dir).ok_or_else(|| {
+        anyhow!(
+            "failed to compute the path from chapter `{chapter}` to `{}`",
+            path.display()
+        )
+    })?;
+    diff_path.into_os_string().into_string().map_err(|url| {
+        anyhow!(
+            "the path from chapter `{chapter}` to `{}` is not valid UTF-8",
+            Path::new(&url).display()
+        )
+    })
+}
+
+/// The chapters linking to a linked chapter, by path of the linked chapter, with their labels and
+/// urls.
+type IndirectBacklinks<'a> =
+    HashMap<&'a NormalizedPathBuf, Vec<(&'a NormalizedPathBuf, String, String)>>;
+
+/// Nest the chapters linking to the chapter at `path` under its entry, and so on recursively.
+fn indirect_list(
+    builder: &mut MarkdownBuilder<'_>,
+    indirect: &IndirectBacklinks<'_>,
+    path: &NormalizedPathBuf,
+    list_start: Option<u64>,
+) {
+    let Some(entries) = indirect.get(path).filter(|entries| !entries.is_empty()) else {
+        return;
+    };
+    builder.tag(Tag::List(list_start), |builder| {
+        for (source, label, url) in entries {
+            builder.tag(Tag::Item, |builder| {
+                builder.simple_link(url.clone(), |builder| {
+                    builder.text(label.clone());
+                });
+                indirect_list(builder, indirect, source, list_start);
+            });
+        }
+    });
+}
+
+/// The labels of the entries of a chapter's backlinks, and the markers that follow them.
+struct EntryLabels<'a> {
+    config: &'a Config,
+    /// The titles of the chapter's headings, by anchor.
+    headings: &'a HashMap<String, String>,
+    /// How many links each backlink stands for after deduplication.
+    repeat_counts: HashMap<&'a Backlink, usize>,
+    /// The number of distinct chapters that share each label.
+    label_counts: HashMap<String, usize>,
+    /// The chapters the chapter links to.
+    linked: HashSet<&'a NormalizedPathBuf>,
+}
+
+impl<'a> EntryLabels<'a> {
+    /// Labels for the `shown` backlinks, out of all the `backlinks` of the chapter.
+    fn new(
+        config: &'a Config,
+        headings: &'a HashMap<String, String>,
+        backlinks: &'a [Backlink],
+        shown: impl Iterator<Item = &'a Backlink>,
+        linked: HashSet<&'a NormalizedPathBuf>,
+    ) -> Self {
+        let repeat_counts = match config.dedup {
+            Dedup::Full => backlinks.iter().counts(),
+            Dedup::Anchor => {
+                let counts = backlinks.iter().counts_by(|b| b.without_fragment());
+                let count = |b: &Backlink| counts[&b.without_fragment()];
+                backlinks.iter().map(|b| (b, count(b))).collect()
+            }
+            Dedup::Path => {
+                let counts = backlinks.iter().counts_by(|b| &b.source.path);
+                backlinks
+                    .iter()
+                    .map(|b| (b, counts[&b.source.path]))
+                    .collect()
+            }
+            Dedup::None => backlinks.iter().map(|b| (b, 1)).collect(),
+        };
+        let mut labels = EntryLabels {
+            config,
+            headings,
+            repeat_counts,
+            label_counts: HashMap::new(),
+            linked,
+        };
+        labels.label_counts = shown
+            .map(|backlink| (labels.base_label(backlink), &backlink.source.path))
+            .unique()
+            .counts_by(|(label, _)| label);
+        labels
+    }
+
+    fn number_prefix(&self, backlink: &Backlink) -> String {
+        match &backlink.source.number {
+            Some(number) if self.config.show_numbers && !number.is_empty() => {
+                format!("{}{}", section_number(number), self.config.number_separator)
+            }
+            _ => String::new(),
+        }
+    }
+
+    fn base_label(&self, backlink: &Backlink) -> String {
+        let label = match self.config.link_text {
+            LinkText::Anchor if !backlink.anchor_text.is_empty() => backlink.anchor_text.clone(),
+            _ if self.config.breadcrumb => {
+                let source = &backlink.source;
+                source.ancestors.iter().chain([&source.title]).join(" > ")
+            }
+            _ => backlink.source.title.clone(),
+        };
+        format!("{}{label}", self.number_prefix(backlink))
+    }
+
+    /// The directory of the linking chapter to prefix its label with under `disambiguate =
+    /// "parent"`.
+    fn parent_prefix(&self, backlink: &Backlink) -> String {
+        let parent = backlink.source.path.relative_to_root().parent();
+        match parent.and_then(Path::file_name) {
+            Some(dir)
+                if self.config.disambiguate == Disambiguate::Parent
+                    && self.label_counts[&self.base_label(backlink)] > 1 =>
+            {
+                format!("{}/", dir.to_string_lossy())
+            }
+            _ => String::new(),
+        }
+    }
+
+    /// The label of a backlink, without its fragment.
+    fn chapter_label(&self, backlink: &Backlink) -> String {
+        let mut label = self.base_label(backlink);
+        if self.label_counts[&label] > 1 {
+            let source = &backlink.source;
+            let hint = match (self.config.disambiguate, &source.number) {
+                (Disambiguate::None | Disambiguate::Parent, _) => None,
+                (Disambiguate::Number, Some(number)) if !number.is_empty() => {
+                    Some(section_number(number))
+                }
+                (Disambiguate::Number | Disambiguate::Path, _) => {
+                    Some(source.path.relative_to_root().display().to_string())
+                }
+            };
+            if let Some(hint) = hint {
+                label = format!("{label} ({hint})");
+            }
+        }
+        label.insert_str(
+            self.number_prefix(backlink).len(),
+            &self.parent_prefix(backlink),
+        );
+        label
+    }
+
+    /// Emit a label of `backlink`, keeping the inline formatting of its anchor text.
+    fn label_text(&self, builder: &mut MarkdownBuilder<'_>, backlink: &Backlink, label: String) {
+        let prefix = self.number_prefix(backlink) + &self.parent_prefix(backlink);
+        let suffix = label
+            .strip_prefix(&prefix)
+            .and_then(|rest| rest.strip_prefix(backlink.anchor_text.as_str()));
+        match suffix {
+            Some(suffix)
+                if self.config.link_text == LinkText::Anchor
+                    && !backlink.anchor_markdown.is_empty() =>
+            {
+                if !prefix.is_empty() {
+                    builder.text(prefix);
+                }
+                for event in inline_events(&backlink.anchor_markdown) {
+                    builder.event(event);
+                }
+                if !suffix.is_empty() {
+                    builder.text(suffix.to_owned());
+                }
+            }
+            _ => builder.text(label),
+        }
+    }
+
+    /// The title of the heading a fragment points to, or the fragment if there is no such heading.
+    fn fragment_heading(&self, fragment: &String) -> String {
+        self.headings.get(fragment).unwrap_or(fragment).clone()
+    }
+
+    /// The text of a link to the linking chapter, or to its `fragment`.
+    fn link_label(
+        &self,
+        builder: &mut MarkdownBuilder<'_>,
+        backlink: &Backlink,
+        url: &str,
+        fragment: Option<&String>,
+    ) {
+        if let Some(format) = &self.config.item_format {
+            let anchor = fragment
+                .map(|fragment| self.fragment_heading(fragment))
+                .unwrap_or_default();
+            let count = self.repeat_counts[backlink];
+            builder.text(format.render(backlink, url, count, &anchor));
+            return;
+        }
+        let mut label = self.chapter_label(backlink);
+        if let Some(fragment) = fragment {
+            label = format!("{label} → {}", self.fragment_heading(fragment));
+        }
+        self.label_text(builder, backlink, label);
+    }
+
+    /// Mark the backlinks from chapters that the chapter links to as well, under `mark_mutual`.
+    fn mutual(&self, builder: &mut MarkdownBuilder<'_>, backlink: &Backlink) {
+        if self.config.mark_mutual && self.linked.contains(&backlink.source.path) {
+            builder.text(" ↔");
+        }
+    }
+
+    /// Show how many links a backlink stands for, under `show_repeat_count`.
+    fn repeats(&self, builder: &mut MarkdownBuilder<'_>, backlink: &Backlink) {
+        let repeats = self.repeat_counts[backlink];
+        if self.config.show_repeat_count && repeats > 1 {
+            builder.text(format!(" ({repeats} links)"));
+        }
+    }
+}
+
+/// Render the backlinks (and forward links) of a chapter, or `None` if it has neither.
+fn render_backlinks(
+    name: &str,
+    content: &str,
+    source_path: &Path,
+    graph: &LinkGraph,
+    config: &Config,
+) -> Result<Option<String>, Error> {
+    let chapter_path = source_path.display();
+    let source_path = graph.chapter_path(source_path)?;
+    let key = config.path_key(&source_path);
+    let Some(backlinks) = graph.backlinks.get(&key) else {
+        return Ok(None);
+    };
+    let forward_links = match graph.forward.get(&key) {
+        Some(links) if config.forward_links => links.as_slice(),
+        _ => &[],
+    };
+    let related = if config.related {
+        graph.related(&key, config.related_count)
+    } else {
+        Vec::new()
+    };
+    if backlinks.is_empty() && forward_links.is_empty() && related.is_empty() && !config.show_empty
+    {
+        return Ok(None);
+    }
+
+    let source_dir = source_path.dir();
+    let link_dir = match config.link_base {
+        LinkBase::Chapter => source_dir,
+        // Normalized paths are relative to `.`, the source directory.
+        LinkBase::Root => Path::new("."),
+    };
+    let relative_url = |path: &NormalizedPathBuf| -> Result<String, Error> {
+        let url = relative_url(path.as_ref(), link_dir, &chapter_path)?;
+        Ok(config.link_url(url))
+    };
+    let link_counts = LinkCounts::of(backlinks);
+    // The fragments of the links each backlink stands for after deduplication, `None` standing
+    // for the links without one.
+    let merged_fragments = |backlink: &Backlink| {
+        backlinks
+            .iter()
+            .filter(|b| match config.dedup {
+                Dedup::Anchor => b.without_fragment() == backlink.without_fragment(),
+                Dedup::Path => b.source.path == backlink.source.path,
+                Dedup::Full | Dedup::None => *b == backlink,
+            })
+            .map(|b| b.fragment.as_ref())
+            .unique()
+            .collect_vec()
+    };
+    let mut backlinks = sorted_backlinks(backlinks, config.sort, config.dedup)
+        .into_iter()
+        .map(|backlink| {
+            let mut url = relative_url(&backlink.source.path)?;
+            if let Some(anchor) = &backlink.source_anchor {
+                url = format!("{url}#{anchor}");
+            }
+            Ok((backlink, url))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    let backlink_count = backlinks.len();
+    if config.max_backlinks != 0 {
+        backlinks.truncate(config.max_backlinks);
+    }
+    let hidden_backlinks = backlink_count - backlinks.len();
+    let forward_links = forward_links
+        .iter()
+        .sorted()
+        .dedup()
+        .map(|link| Ok((link, relative_url(&link.path)?)))
+        .collect::<Result<Vec<_>, Error>>()?;
+    let related = related
+        .into_iter()
+        .map(|info| Ok((info, relative_url(&info.path)?)))
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    if let Some(template) = &config.template_source {
+        return render_template(
+            template,
+            name,
+            &source_path,
+            &backlinks,
+            &forward_links,
+            &related,
+        )
+        .map(Some);
+    }
+    // The backlinks with a relationship go in their own sections.
+    let (backlinks, tagged_backlinks): (Vec<_>, Vec<_>) = backlinks
+        .into_iter()
+        .partition(|(backlink, _)| backlink.relationship.is_none());
+
+    let headings = heading_titles(content);
+    let heading = config.heading_for(&source_path);
+    let list_start = (config.list_style == ListStyle::Numbered).then_some(1);
+    // A simpler block for single-file markdown output.
+    let plain = config.renderer == "markdown" && config.markdown_style == MarkdownStyle::Plain;
+    let heading_level = if plain {
+        HeadingLevel::H2
+    } else {
+        config.heading_level
+    };
+    // Classes and ids are only meaningful in HTML.
+    let html_hooks = config.renderer == "html" && !config.css_class.is_empty();
+    let heading_id =
+        |suffix: &str| html_hooks.then(|| format!("{}-{suffix}", config.css_class).into());
+    // The id of the backlinks heading, made distinct from the ids of the chapter's own headings.
+    let backlinks_id = (config.renderer == "html" && !config.heading_id.is_empty()).then(|| {
+        let id = &config.heading_id;
+        let id = (0..)
+            .map(|n| match n {
+                0 => id.clone(),
+                n => format!("{id}-{n}"),
+            })
+            .find(|id| !headings.contains_key(id))
+            .unwrap();
+        CowStr::from(id)
+    });
+    // The chapters this one links to.
+    let linked = graph.forward[&key].iter().map(|info| &info.path).collect();
+    let shown = backlinks.iter().chain(&tagged_backlinks).map(|(b, _)| *b);
+    let labels = EntryLabels::new(config, &headings, &graph.backlinks[&key], shown, linked);
+    // The chapters linking to the linking chapters, further away at each level of `depth`. Each
+    // chapter is listed once, at its first and closest place, which also stops at cycles.
+    let mut indirect = IndirectBacklinks::new();
+    // The entry each linking chapter's own backlinks are nested under.
+    let mut nested_under = HashMap::new();
+    for (backlink, _) in backlinks.iter().chain(&tagged_backlinks) {
+        nested_under
+            .entry(&backlink.source.path)
+            .or_insert(*backlink);
+    }
+    let mut seen: HashSet<_> = graph.backlinks[&key]
+        .iter()
+        .map(|b| &b.source.path)
+        .collect();
+    seen.insert(&source_path);
+    let mut frontier = backlinks
+        .iter()
+        .chain(&tagged_backlinks)
+        .map(|(b, _)| &b.source.path)
+        .unique()
+        .collect_vec();
+    for _ in 1..config.depth {
+        let mut next = Vec::new();
+        for path in frontier {
+            let sources = sorted_backlinks(
+                &graph.backlinks[&config.path_key(path)],
+                config.sort,
+                Dedup::Path,
+            );
+            let mut entries = Vec::new();
+            for backlink in sources {
+                let source = &backlink.source;
+                if seen.insert(&source.path) {
+                    let label = format!("{}{}", labels.number_prefix(backlink), source.title);
+                    entries.push((&source.path, label, relative_url(&source.path)?));
+                    next.push(&source.path);
+                }
+            }
+            indirect.insert(path, entries);
+        }
+        frontier = next;
+    }
+    let nested = |builder: &mut MarkdownBuilder, backlink: &Backlink| {
+        if std::ptr::eq(nested_under[&backlink.source.path], backlink) {
+            indirect_list(builder, &indirect, &backlink.source.path, list_start);
+        }
+    };
+    // The link to the linking chapter, and the markers that follow it.
+    let backlink_entry = |builder: &mut MarkdownBuilder, backlink: &Backlink, url: &str| {
+        builder.link(url.to_owned(), backlink.title.clone(), |builder| {
+            labels.link_label(builder, backlink, url, backlink.fragment.as_ref());
+        });
+        labels.mutual(builder, backlink);
+        labels.repeats(builder, backlink);
+    };
+    let backlink_item = |builder: &mut MarkdownBuilder, backlink: &Backlink, url: &str| {
+        builder.tag(Tag::Item, |builder| {
+            backlink_entry(builder, backlink, url);
+            nested(builder, backlink);
+        });
+    };
+    let backlink_table = |builder: &mut MarkdownBuilder, backlinks: &[&(&Backlink, String)]| {
+        builder.tag(Tag::Table(vec![Alignment::None; 2]), |builder| {
+            builder.tag(Tag::TableHead, |builder| {
+                for title in ["Page", "Section"] {
+                    builder.tag(Tag::TableCell, |builder| builder.text(title));
+                }
+            });
+            for (backlink, url) in backlinks {
+                builder.tag(Tag::TableRow, |builder| {
+                    builder.tag(Tag::TableCell, |builder| {
+                        backlink_entry(builder, backlink, url);
+                    });
+                    builder.tag(Tag::TableCell, |builder| {
+                        if let Some(number) = &backlink.source.number {
+                            builder.text(section_number(number));
+                        }
+                    });
+                });
+            }
+        });
+    };
+    // A single entry for several backlinks from the same chapter, with a sub-item for
+    // each of the anchors they link to.
+    let anchors_item = |builder: &mut MarkdownBuilder, group: &[&(&Backlink, String)]| {
+        let (first, url) = group[0];
+        builder.tag(Tag::Item, |builder| {
+            builder.simple_link(url.clone(), |builder| {
+                labels.link_label(builder, first, url, None);
+            });
+            labels.mutual(builder, first);
+            if let Some((backlink, _)) = group.iter().find(|(b, _)| b.fragment.is_none()) {
+                labels.repeats(builder, backlink);
+            }
+            builder.tag(Tag::List(list_start), |builder| {
+                for (backlink, url) in group {
+                    let Some(fragment) = &backlink.fragment else {
+                        continue;
+                    };
+                    builder.tag(Tag::Item, |builder| {
+                        builder.link(url.clone(), backlink.title.clone(), |builder| {
+                            builder.text(labels.fragment_heading(fragment));
+                        });
+                        labels.repeats(builder, backlink);
+                    });
+                }
+            });
+            nested(builder, first);
+        });
+    };
+    // A single entry for a backlink that stands for links to several anchors, with a sub-item for
+    // each of the anchors under `group_anchors`.
+    let merged_item = |builder: &mut MarkdownBuilder, backlink: &Backlink, url: &str| {
+        let fragments = merged_fragments(backlink);
+        if let [Some(_)] = fragments[..] {
+            // All the links point to the same anchor.
+            return backlink_item(builder, backlink, url);
+        }
+        builder.tag(Tag::Item, |builder| {
+            builder.link(url.to_owned(), backlink.title.clone(), |builder| {
+                labels.link_label(builder, backlink, url, None);
+            });
+            labels.mutual(builder, backlink);
+            labels.repeats(builder, backlink);
+            if config.group_anchors && fragments.iter().any(Option::is_some) {
+                builder.tag(Tag::List(list_start), |builder| {
+                    for fragment in fragments.into_iter().flatten() {
+                        builder.tag(Tag::Item, |builder| {
+                            builder.simple_link(url.to_owned(), |builder| {
+                                builder.text(labels.fragment_heading(fragment));
+                            });
+                        });
+                    }
+                });
+            }
+            nested(builder, backlink);
+        });
+    };
+    let backlink_items = |builder: &mut MarkdownBuilder, backlinks: &[&(&Backlink, String)]| {
+        if matches!(config.dedup, Dedup::Anchor | Dedup::Path) {
+            for (backlink, url) in backlinks {
+                merged_item(builder, backlink, url);
+            }
+            return;
+        }
+        if !config.group_anchors {
+            for (backlink, url) in backlinks {
+                backlink_item(builder, backlink, url);
+            }
+            return;
+        }
+        let sources = backlinks.iter().map(|(b, _)| &b.source.path).unique();
+        for source in sources {
+            let group = backlinks
+                .iter()
+                .filter(|(b, _)| &b.source.path == source)
+                .copied()
+                .collect_vec();
+            if group.iter().filter(|(b, _)| b.fragment.is_some()).count() > 1 {
+                anchors_item(builder, &group);
+            } else {
+                for (backlink, url) in group {
+                    backlink_item(builder, backlink, url);
+                }
+            }
+        }
+    };
+    let body = |builder: &mut MarkdownBuilder| {
+        if !backlinks.is_empty() {
+            let collapsible = config.collapsible && config.renderer == "html";
+            if collapsible {
+                // The summary is all that shows of a closed block, so it can't be empty.
+                let summary = if heading.is_empty() {
+                    backlink_count.to_string()
+                } else {
+                    format!("{heading} ({backlink_count})")
+                };
+                let summary = escape_html(&summary);
+                builder.event(Event::Html(
+                    format!("<details>\n<summary>{summary}</summary>\n\n").into(),
+                ));
+            } else if !heading.is_empty() && config.container != Container::None {
+                let heading = match config.show_count {
+                    ShowCount::Off => heading.to_owned(),
+                    ShowCount::Total => format!("{heading} ({backlink_count})"),
+                    ShowCount::Detailed => {
+                        let LinkCounts { links, pages } = link_counts;
+                        let pages = if pages == 1 {
+                            "1 page".to_owned()
+                        } else {
+                            format!("{pages} pages")
+                        };
+                        format!("{heading} ({links} from {pages})")
+                    }
+                };
+                builder.heading(heading_level, backlinks_id.clone(), |builder| {
+                    builder.text(heading);
+                });
+            }
+            if !config.intro_text.is_empty() {
+                builder.tag(Tag::Paragraph, |builder| {
+                    builder.text(config.intro_text.clone());
+                });
+            }
+            if config.layout == Layout::Table {
+                backlink_table(builder, &backlinks.iter().collect_vec());
+                if hidden_backlinks != 0 {
+                    builder.tag(Tag::Paragraph, |builder| {
+                        builder.text(format!("…and {hidden_backlinks} more"));
+                    });
+                }
+            } else {
+                builder.tag(Tag::List(list_start), |builder| {
+                    if config.group_by_part {
+                        // Group by top-level section number, with unnumbered chapters last.
+                        let groups = backlinks
+                            .iter()
+                            .into_group_map_by(|(b, _)| b.source.number.as_ref()?.first().copied());
+                        let groups = groups
+                            .into_iter()
+                            .sorted_by_key(|(part, _)| (part.is_none(), *part));
+                        for (part, backlinks) in groups {
+                            let title = match part {
+                                Some(part) => match graph.part_title(part) {
+                                    Some(title) => title.to_owned(),
+                                    None => format!("Part {}", section_number(&[part])),
+                                },
+                                None => "Ungrouped".to_owned(),
+                            };
+                            builder.tag(Tag::Item, |builder| {
+                                builder.text(title);
+                                builder.tag(Tag::List(list_start), |builder| {
+                                    backlink_items(builder, &backlinks);
+                                });
+                            });
+                        }
+                    } else {
+                        backlink_items(builder, &backlinks.iter().collect_vec());
+                    }
+                    if hidden_backlinks != 0 {
+                        builder.tag(Tag::Item, |builder| {
+                            builder.text(format!("…and {hidden_backlinks} more"));
+                        });
+                    }
+                });
+            }
+            if collapsible {
+                builder.event(Event::Html("\n</details>\n".into()));
+            }
+        } else if config.show_empty && tagged_backlinks.is_empty() {
+            if !heading.is_empty() && config.container != Container::None {
+                builder.heading(heading_level, backlinks_id.clone(), |builder| {
+                    builder.text(heading.to_owned());
+                });
+            }
+            builder.tag(Tag::Paragraph, |builder| {
+                builder.text(config.empty_text.clone());
+            });
+        }
+        for relationship in &config.relationships {
+            let backlinks = tagged_backlinks
+                .iter()
+                .filter(|(b, _)| b.relationship.as_ref() == Some(&relationship.name))
+                .collect_vec();
+            if backlinks.is_empty() {
+                continue;
+            }
+            let id = heading_id(&format!("{}-heading", relationship.name));
+            builder.heading(heading_level, id, |builder| {
+                builder.text(relationship.heading.clone());
+            });
+            if config.layout == Layout::Table {
+                backlink_table(builder, &backlinks);
+            } else {
+                builder.tag(Tag::List(list_start), |builder| {
+                    backlink_items(builder, &backlinks);
+                });
+            }
+        }
+        // A list of links to chapters, like the forward links.
+        let chapter_list = |builder: &mut MarkdownBuilder<'_>,
+                            heading: &str,
+                            id: &str,
+                            links: &[(&Rc<ChapterInfo>, String)]| {
+            if links.is_empty() {
+                return;
+            }
+            if !heading.is_empty() {
+                builder.heading(heading_level, heading_id(id), |builder| {
+                    builder.text(heading.to_owned());
+                });
+            }
+            builder.tag(Tag::List(list_start), |builder| {
+                for (link, url) in links {
+                    builder.tag(Tag::Item, |builder| {
+                        builder.simple_link(url.clone(), |builder| {
+                            builder.text(link.title.clone());
+                        });
+                    });
+                }
+            });
+        };
+        chapter_list(
+            builder,
+            &config.forward_heading,
+            "forward-heading",
+            &forward_links,
+        );
+        chapter_list(
+            builder,
+            &config.related_heading,
+            "related-heading",
+            &related,
+        );
+    };
+    let mut builder = MarkdownBuilder::default();
+    // The rule goes between the chapter and the block.
+    let separator = config.separator && !plain && config.container != Container::None;
+    if separator && config.position == Position::Bottom {
+        builder.event(Event::Rule);
+    }
+    if plain || config.container != Container::Blockquote {
+        body(&mut builder);
+    } else {
+        builder.tag(Tag::BlockQuote(config.callout.kind()), body);
+    }
+    if separator && config.position == Position::Top {
+        builder.event(Event::Rule);
+    }
+    let mut rendered = String::new();
+    builder.write_to_string(&mut rendered);
+    let mut rendered = rendered.trim_start_matches('\n').to_owned();
+    if html_hooks {
+        let class = escape_html(&config.css_class);
+        rendered = format!("<div class=\"{class}\">\n\n{rendered}\n\n</div>");
+    }
+    Ok(Some(rendered))
+}
+
+/// Print a warning for each of the chapters that no chapter links to.
+fn warn_orphans(orphans: &[&Path]) {
+    for path in orphans {
+        eprintln!("Warning: no chapter links to {}", path.display());
+    }
+}
+
+/// Collect the links of the book, write the reports asked for by the configuration, and add
+/// backlinks to its chapters.
+fn process_book(mut book: Book, config: &Config) -> Result<Book, Error> {
+    // In report-only mode we give back the book exactly as we got it.
+    let input = config.report_only.then(|| book.clone());
+    remove_backlinks_blocks(&mut book);
+    let graph = LinkGraph::collect(&book, config)?;
+    let src_dir = &config.src_dir;
+    if let Some(graph_output) = &config.graph_output {
+        std::fs::write(src_dir.join(graph_output), graph.to_dot())?;
+    }
+    if let Some(json_output) = &config.json_output {
+        std::fs::write(src_dir.join(json_output), graph.to_json()?)?;
+    }
+    if let Some(counts_output) = &config.counts_output {
+        std::fs::write(src_dir.join(counts_output), graph.counts_to_json()?)?;
+    }
+    if config.check_orphans || config.fail_on_orphans || config.orphans_output.is_some() {
+        let orphans = graph.orphans(config)?;
+        // In report-only mode we report the orphans instead of failing on them.
+        if config.check_orphans || (config.fail_on_orphans && config.report_only) {
+            warn_orphans(&orphans);
+        }
+        if let Some(orphans_output) = &config.orphans_output {
+            let list = orphans
+                .iter()
+                .map(|path| format!("{}\n", path.display()))
+                .join("");
+            std::fs::write(src_dir.join(orphans_output), list)?;
+        }
+        if config.fail_on_orphans && !config.report_only && !orphans.is_empty() {
+            bail!(
+                "found {} chapter(s) that no chapter links to: {}. Link to them, or list them in \
+                 `orphan_ignore`",
+                orphans.len(),
+                orphans.iter().map(|path| path.display()).join(", ")
+            );
+        }
+    }
+    if config.hub_report != 0 {
+        let report = graph
+            .hubs(config.hub_report, config.dedup)
+            .into_iter()
+            .map(|(info, count)| {
+                format!(
+                    "{}\t{}\t{count}\n",
+                    info.path.relative_to_root().display(),
+                    info.name
+                )
+            })
+            .join("");
+        match &config.hub_report_output {
+            Some(output) => std::fs::write(src_dir.join(output), report)?,
+            None => eprint!("{report}"),
+        }
+    }
+    if let Some(input) = input {
+        for (info, count) in graph.hubs(usize::MAX, config.dedup) {
+            eprintln!(
+                "{}\t{}\t{count}",
+                info.path.relative_to_root().display(),
+                info.name
+            );
+        }
+        return Ok(input);
+    }
+    let mut book = add_backlinks(book, &graph, config)?;
+    add_index_chapter(&mut book, &graph, config)?;
+    Ok(book)
+}
+
+/// The preprocessor, configured from the `[preprocessor.backlinks]` table of `book.toml`.
+pub struct Backlinks;
+impl Preprocessor for Backlinks {
+    fn name(&self) -> &str {
+        "backlinks"
+    }
+
+    fn run(&self, ctx: &PreprocessorContext, book: Book) -> Result<Book, Error> {
+        process_book(book, &Config::from_context(ctx)?)
+    }
+}
+
+/// Collect the backlinks of every chapter of the book, keyed by normalized chapter path, with the
+/// default configuration.
+pub fn collect_backlinks(book: &Book) -> Result<HashMap<NormalizedPathBuf, Vec<Backlink>>, Error> {
+    Ok(LinkGraph::collect(book, &Config::default())?.backlinks)
+}
+
+/// Whether a book with the given configuration wants backlinks for the given renderer.
+pub fn supports_renderer(config: &BookConfig, renderer: &str) -> Result<bool, Error> {
+    Ok(Config::from_book_config(config)?.supports_renderer(renderer))
+}
+
+/// Report the broken internal links of the book, and fail if there are any. With `check_orphans`
+/// or `fail_on_orphans`, also warn about the chapters that no chapter links to.
+pub fn check_links(ctx: &PreprocessorContext, mut book: Book) -> Result<(), Error> {
+    let mut config = Config::from_context(ctx)?;
+    config.check_broken = true;
+    config.fail_on_broken = true;
+    remove_backlinks_blocks(&mut book);
+    let graph = LinkGraph::collect(&book, &config)?;
+    // Orphans aren't broken links: report them, but don't fail the check.
+    if config.check_orphans || config.fail_on_orphans {
+        warn_orphans(&graph.orphans(&config)?);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+fn mk_chap(name: &str, path: &str, contents: &str, number: Vec<u32>) -> BookItem {
+    use mdbook_preprocessor::book::SectionNumber;
+    let mut ch = Chapter::new(name, contents.into(), path, vec![]);
+    ch.number = Some(SectionNumber::new(number));
+    BookItem::Chapter(ch)
+}
+
+/// Parses a `[preprocessor.backlinks]` table.
+#[cfg(test)]
+fn mk_config(toml: &str) -> Config {
+    toml::from_str(toml).unwrap()
+}
+
+/// Returns the content of the chapter with the given source path.
+#[cfg(test)]
+fn chapter_content<'a>(book: &'a Book, path: &str) -> &'a str {
+    book.iter()
+        .find_map(|item| match item {
+            BookItem::Chapter(ch) if ch.source_path.as_deref() == Some(Path::new(path)) => {
+                Some(ch.content.as_str())
+            }
+            _ => None,
+        })
+        .unwrap()
+}
+
+#[test]
+fn test() {
+    let mut book = Book::new();
+    book.push_item(mk_chap(
+        "index",
+        "index.md",
+        "[link](b/last_chapter.md)",
+        vec![0],
+    ));
+    book.push_item(mk_chap(
+        "ch1",
+        "a/ch1.md",
+        "[link](../b/last_chapter.md)",
+        vec![1, 1],
+    ));
+    book.push_item(mk_chap(
+        "ch2",
+        "b/ch2.md",
+        "[link](last_chapter.md)",
+        vec![2, 2], // twist
+    ));
+    book.push_item(mk_chap(
+        "ch3",
+        "b/ch3.md",
+        "[link](last_chapter.md)",
+        vec![2, 1],
+    ));
+    book.push_item(mk_chap("last_chapter", "b/last_chapter.md", "", vec![2, 3]));
+    let book = process_book(book, &Config::default()).unwrap();
+
+    assert_eq!(
+        chapter_content(&book, "b/last_chapter.md"),
+        indoc::indoc!(
+            "
+
+
+            <!-- backlinks -->
+
+            <div class=\"mdbook-backlinks\">
+
+            ---
+
+             > 
+             > #### Backlinks { #backlinks }
+             > 
+             > * [index](../index.md)
+             > * [ch1](../a/ch1.md)
+             > * [ch3](ch3.md)
+             > * [ch2](ch2.md)
+
+            </div>
+
+            <!-- /backlinks -->"
+        )
+    );
+}
+
+#[test]
+fn test_heading() {
+    let mk_book = || {
+        Book::new_with_items(vec![
+            mk_chap("ch1", "ch1.md", "[link](ch2.md)", vec![1]),
+            mk_chap("ch2", "ch2.md", "", vec![2]),
+        ])
+    };
+
+    let book = process_book(mk_book(), &mk_config(r#"heading = "Pages liées""#)).unwrap();
+    assert!(chapter_content(&book, "ch2.md").contains("#### Pages liées"));
+
+    let book = process_book(mk_book(), &mk_config(r#"heading = """#)).unwrap();
+    assert!(!chapter_content(&book, "ch2.md").contains("####"));
+    assert!(chapter_content(&book, "ch2.md").contains("* [ch1](ch1.md)"));
+}
+
+#[test]
+fn test_heading_level() {
+    assert_eq!(mk_config("").heading_level, HeadingLevel::H4);
+    assert_eq!(
+        mk_config("heading_level = 2").heading_level,
+        HeadingLevel::H2
+    );
+    assert_eq!(
+        mk_config(r#"heading_level = "h3""#).heading_level,
+        HeadingLevel::H3
+    );
+    for invalid in ["heading_level = 0", r#"heading_level = "h7""#] {
+        let err = toml::from_str::<Config>(invalid).unwrap_err();
+        assert!(err.to_string().contains("invalid heading level"), "{err}");
+    }
+
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "ch1.md", "[link](ch2.md)", vec![1]),
+        mk_chap("ch2", "ch2.md", "", vec![2]),
+    ]);
+    let book = process_book(book, &mk_config("heading_level = 2")).unwrap();
+    assert!(chapter_content(&book, "ch2.md").contains(" > ## Backlinks"));
+}
+
+#[test]
+fn test_position_top() {
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "ch1.md", "[link](ch2.md)", vec![1]),
+        mk_chap("ch2", "ch2.md", "Content\n", vec![2]),
+    ]);
+    let book = process_book(book, &mk_config(r#"position = "top""#)).unwrap();
+    assert_eq!(
+        chapter_content(&book, "ch2.md"),
+        indoc::indoc!(
+            "
+            <!-- backlinks -->
+
+            <div class=\"mdbook-backlinks\">
+
+             > 
+             > #### Backlinks { #backlinks }
+             > 
+             > * [ch1](ch1.md)
+
+            ---
+
+            </div>
+
+            <!-- /backlinks -->
+
+            Content
+            "
+        )
+    );
+}
+
+#[test]
+fn test_fragment() {
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "a/ch1.md", "[link](../b/ch2.md#intro)", vec![1]),
+        mk_chap("ch2", "b/ch2.md", "", vec![2]),
+    ]);
+    let book = process_book(book, &Config::default()).unwrap();
+    assert!(chapter_content(&book, "b/ch2.md").contains("* [ch1 → intro](../a/ch1.md)"));
+}
+
+#[test]
+fn test_fragment_label() {
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "ch1.md", "[link](ch2.md#installation)", vec![1]),
+        mk_chap(
+            "ch2",
+            "ch2.md",
+            "[link](ch1.md#missing)\n\n## Installation",
+            vec![2],
+        ),
+        mk_chap("ch3", "ch3.md", "[link](ch2.md)", vec![3]),
+    ]);
+    let book = process_book(book, &Config::default()).unwrap();
+    assert!(chapter_content(&book, "ch1.md").contains("* [ch2 → missing](ch2.md)"));
+    let ch2 = chapter_content(&book, "ch2.md");
+    assert!(ch2.contains("* [ch1 → Installation](ch1.md)"), "{ch2}");
+    assert!(ch2.contains("* [ch3](ch3.md)"), "{ch2}");
+}
+
+#[test]
+fn test_external_links() {
+    for url in [
+        "https://example.com",
+        "http://example.com/ch2.md",
+        "mailto:x@y.com",
+        "ftp://example.com",
+        "//example.com/ch2.md",
+    ] {
+        assert!(is_external_link(url), "{url}");
+    }
+    for url in ["ch2.md", "../b/ch2.md", "ch2.md#a:b", "/ch2.md", "#intro"] {
+        assert!(!is_external_link(url), "{url}");
+    }
+}
+
+#[test]
+fn test_reference_links() {
+    let book = Book::new_with_items(vec![
+        mk_chap(
+            "reference",
+            "a/reference.md",
+            "[link][target]\n\n[target]: ../b/last_chapter.md",
+            vec![1],
+        ),
+        mk_chap(
+            "collapsed",
+            "a/collapsed.md",
+            "[target][]\n\n[target]: ../b/last_chapter.md",
+            vec![2],
+        ),
+        mk_chap(
+            "shortcut",
+            "a/shortcut.md",
+            "[last_chapter]\n\n[last_chapter]: ../b/last_chapter.md",
+            vec![3],
+        ),
+        mk_chap("last_chapter", "b/last_chapter.md", "", vec![4]),
+    ]);
+    let book = process_book(book, &Config::default()).unwrap();
+    let content = chapter_content(&book, "b/last_chapter.md");
+    assert!(
+        content.contains("* [reference](../a/reference.md)"),
+        "{content}"
+    );
+    assert!(
+        content.contains("* [collapsed](../a/collapsed.md)"),
+        "{content}"
+    );
+    assert!(
+        content.contains("* [shortcut](../a/shortcut.md)"),
+        "{content}"
+    );
+}
+
+#[test]
+fn test_fail_on_broken() {
+    let mk_book = |content| {
+        Book::new_with_items(vec![
+            mk_chap("ch1", "a/ch1.md", content, vec![1]),
+            mk_chap("ch2", "b/ch2.md", "", vec![2]),
+        ])
+    };
+    let config = mk_config("fail_on_broken = true");
+    let links = "[ok](../b/ch2.md) [ok](#anchor) [ok](https://example.com)";
+    assert!(process_book(mk_book(links), &config).is_ok());
+    let err = process_book(mk_book("[broken](ch2.md)"), &config).unwrap_err();
+    assert_eq!(err.to_string(), "found 1 broken internal link(s)");
+}
+
+#[test]
+fn test_forward_links() {
+    let mk_book = || {
+        Book::new_with_items(vec![
+            mk_chap(
+                "ch1",
+                "a/ch1.md",
+                "[x](../b/ch2.md) [y](../b/ch2.md#a)",
+                vec![1],
+            ),
+            mk_chap("ch2", "b/ch2.md", "", vec![2]),
+        ])
+    };
+    let book = process_book(mk_book(), &Config::default()).unwrap();
+    assert_eq!(
+        chapter_content(&book, "a/ch1.md"),
+        "[x](../b/ch2.md) [y](../b/ch2.md#a)"
+    );
+
+    let book = process_book(mk_book(), &mk_config("forward_links = true")).unwrap();
+    assert_eq!(
+        chapter_content(&book, "a/ch1.md"),
+        indoc::indoc!(
+            "
+            [x](../b/ch2.md) [y](../b/ch2.md#a)
+
+            <!-- backlinks -->
+
+            <div class=\"mdbook-backlinks\">
+
+            ---
+
+             > 
+             > #### Links { #mdbook-backlinks-forward-heading }
+             > 
+             > * [ch2](../b/ch2.md)
+
+            </div>
+
+            <!-- /backlinks -->"
+        )
+    );
+    // Chapters without forward links only get their backlinks.
+    assert!(!chapter_content(&book, "b/ch2.md").contains("#### Links"));
+}
+
+#[test]
+fn test_graph() {
+    let book = Book::new_with_items(vec![
+        mk_chap(
+            "ch1",
+            "a/ch1.md",
+            "[x](../b/ch2.md) [y](../b/ch2.md#a)",
+            vec![1],
+        ),
+        mk_chap("ch \"2\"", "b/ch2.md", "[x](../a/ch1.md)", vec![2]),
+    ]);
+    let graph = LinkGraph::collect(&book, &Config::default()).unwrap();
+    assert_eq!(
+        graph.to_dot(),
+        indoc::indoc!(
+            r#"
+            digraph backlinks {
+                "./a/ch1.md" [label="ch1"];
+                "./b/ch2.md" [label="ch \"2\""];
+                "./a/ch1.md" -> "./b/ch2.md";
+                "./b/ch2.md" -> "./a/ch1.md";
+            }
+            "#
+        )
+    );
+}
+
+#[test]
+fn test_json() {
+    let book = Book::new_with_items(vec![
+        mk_chap(
+            "ch1",
+            "a/ch1.md",
+            "[x](../b/ch2.md) [y](../b/ch2.md#a)",
+            vec![1],
+        ),
+        mk_chap("ch2", "b/ch2.md", "", vec![2, 1]),
+    ]);
+    let graph = LinkGraph::collect(&book, &Config::default()).unwrap();
+    let json: serde_json::Value = serde_json::from_str(&graph.to_json().unwrap()).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!({
+            "a/ch1.md": [],
+            "b/ch2.md": [{ "name": "ch1", "path": "a/ch1.md", "number": [1] }],
+        })
+    );
+}
+
+#[test]
+fn test_show_count() {
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "ch1.md", "[x](ch3.md) [y](ch3.md)", vec![1]),
+        mk_chap("ch2", "ch2.md", "[x](ch3.md)", vec![2]),
+        mk_chap("ch3", "ch3.md", "", vec![3]),
+    ]);
+    let book = process_book(book, &mk_config("show_count = true")).unwrap();
+    assert!(chapter_content(&book, "ch3.md").contains("#### Backlinks (2)"));
+}
+
+#[test]
+fn test_show_count_detailed() {
+    let book = Book::new_with_items(vec![
+        mk_chap(
+            "ch1",
+            "ch1.md",
+            "[x](ch3.md) [y](ch3.md) [x](ch2.md)",
+            vec![1],
+        ),
+        mk_chap("ch2", "ch2.md", "[x](ch3.md)", vec![2]),
+        mk_chap("ch3", "ch3.md", "", vec![3]),
+    ]);
+    let config = mk_config(r#"show_count = "detailed""#);
+    let graph = LinkGraph::collect(&book, &config).unwrap();
+    let counts: serde_json::Value = serde_json::from_str(&graph.counts_to_json().unwrap()).unwrap();
+    assert_eq!(
+        counts["ch3.md"],
+        serde_json::json!({ "links": 3, "pages": 2 })
+    );
+    assert_eq!(
+        counts["ch2.md"],
+        serde_json::json!({ "links": 1, "pages": 1 })
+    );
+
+    let book = process_book(book, &config).unwrap();
+    assert!(chapter_content(&book, "ch3.md").contains("#### Backlinks (3 from 2 pages)"));
+    assert!(chapter_content(&book, "ch2.md").contains("#### Backlinks (1 from 1 page)"));
+}
+
+#[test]
+fn test_sort() {
+    let book = Book::new_with_items(vec![
+        mk_chap("b", "c.md", "[x](target.md)", vec![1]),
+        mk_chap("c", "a.md", "[x](target.md)", vec![3]),
+        mk_chap("a", "b.md", "[x](target.md)", vec![2]),
+        mk_chap("target", "target.md", "", vec![4]),
+    ]);
+    let order = |sort: &str| {
+        let config = mk_config(&format!("sort = \"{sort}\""));
+        let book = process_book(book.clone(), &config).unwrap();
+        chapter_content(&book, "target.md")
+            .lines()
+            .filter_map(|line| line.strip_prefix(" > * ["))
+            .map(|line| &line[..1])
+            .collect::<String>()
+    };
+    assert_eq!(order("number"), "bac");
+    assert_eq!(order("name"), "abc");
+    assert_eq!(order("path"), "cab");
+    assert_eq!(order("document"), "bca");
+}
+
+#[test]
+fn test_link_text_anchor() {
+    let book = Book::new_with_items(vec![
+        mk_chap(
+            "ch1",
+            "ch1.md",
+            "[see the *final* chapter](ch2.md) [![img](x.png)](ch2.md)",
+            vec![1],
+        ),
+        mk_chap("ch2", "ch2.md", "", vec![2]),
+    ]);
+    let book = process_book(book, &mk_config(r#"link_text = "anchor""#)).unwrap();
+    let content = chapter_content(&book, "ch2.md");
+    assert!(
+        content.contains("* [see the *final* chapter](ch1.md)"),
+        "{content}"
+    );
+    assert!(content.contains("* [img](ch1.md)"), "{content}");
+}
+
+#[test]
+fn test_idempotent() {
+    for position in ["top", "bottom"] {
+        let book = Book::new_with_items(vec![
+            mk_chap("ch1", "ch1.md", "[link](ch2.md)", vec![1]),
+            mk_chap("ch2", "ch2.md", "Content\n", vec![2]),
+        ]);
+        let config = mk_config(&format!("position = \"{position}\""));
+        let once = process_book(book, &config).unwrap();
+        let twice = process_book(once.clone(), &config).unwrap();
+        assert_eq!(once, twice);
+        assert_eq!(
+            chapter_content(&twice, "ch2.md")
+                .matches(BLOCK_START)
+                .count(),
+            1
+        );
+        // The links inside the block of ch2 don't count as backlinks of ch1.
+        assert_eq!(chapter_content(&twice, "ch1.md"), "[link](ch2.md)");
+    }
+}
+
+#[test]
+fn test_rerun_after_other_preprocessors() {
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "ch1.md", "[link](ch2.md)", vec![1]),
+        mk_chap("ch2", "ch2.md", "Content\n", vec![2]),
+    ]);
+    let config = Config::default();
+    let mut once = process_book(book, &config).unwrap();
+    // Another preprocessor that runs after us adds a footer.
+    once.for_each_mut(|item| {
+        if let BookItem::Chapter(ch) = item {
+            ch.content += "\n\nFooter";
+        }
+    });
+    let twice = process_book(once.clone(), &config).unwrap();
+    let thrice = process_book(twice.clone(), &config).unwrap();
+    assert_eq!(twice, thrice);
+    let content = chapter_content(&twice, "ch2.md");
+    assert_eq!(content.matches(BLOCK_END).count(), 1, "{content}");
+    assert!(content.starts_with("Content\n"), "{content}");
+    assert!(content.contains("* [ch1](ch1.md)"), "{content}");
+    assert!(
+        content.ends_with(&format!("{BLOCK_END}\n\nFooter")),
+        "{content}"
+    );
+}
+
+#[test]
+fn test_relative_url_error() {
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "ch1.md", "[link](/ch2.md)", vec![1]),
+        mk_chap("ch2", "/ch2.md", "", vec![2]),
+    ]);
+    let err = process_book(book, &Config::default()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "failed to compute the path from chapter `/ch2.md` to `./ch1.md`"
+    );
+}
+
+#[test]
+fn test_root_level_chapters() {
+    let book = Book::new_with_items(vec![
+        mk_chap("index", "index.md", "[x](a/b/ch1.md)", vec![1]),
+        mk_chap("ch1", "a/b/ch1.md", "[x](../../index.md)", vec![2]),
+    ]);
+    let book = process_book(book, &Config::default()).unwrap();
+    let content = chapter_content(&book, "index.md");
+    assert!(content.contains("* [ch1](a/b/ch1.md)"), "{content}");
+    let content = chapter_content(&book, "a/b/ch1.md");
+    assert!(content.contains("* [index](../../index.md)"), "{content}");
+}
+
+#[test]
+fn test_wikilinks() {
+    assert_eq!(
+        wikilinks("[[a]] [[b|label]] [c] [[d"),
+        vec![("a", "a"), ("b", "label")]
+    );
+
+    let mk_book = || {
+        Book::new_with_items(vec![
+            mk_chap("ch1", "b/ch1.md", "[[last_chapter]]", vec![1]),
+            mk_chap(
+                "ch2",
+                "b/ch2.md",
+                "[[last_chapter.md#intro|the end]]",
+                vec![2],
+            ),
+            mk_chap("ch3", "b/ch3.md", "`[[last_chapter]]`", vec![3]),
+            mk_chap("last_chapter", "b/last_chapter.md", "", vec![4]),
+        ])
+    };
+    let book = process_book(mk_book(), &Config::default()).unwrap();
+    assert_eq!(chapter_content(&book, "b/last_chapter.md"), "");
+
+    let book = process_book(mk_book(), &mk_config("wikilinks = true")).unwrap();
+    let content = chapter_content(&book, "b/last_chapter.md");
+    assert!(content.contains("* [ch1](ch1.md)"), "{content}");
+    assert!(content.contains("* [ch2 → intro](ch2.md)"), "{content}");
+    assert!(!content.contains("ch3"), "{content}");
+}
+
+#[test]
+fn test_wikilinks_dedup() {
+    // A wikilink and a normal link to the same chapter make a single backlink.
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "ch1.md", "[x](ch2.md) [[ch2]]", vec![1]),
+        mk_chap("ch2", "ch2.md", "", vec![2]),
+    ]);
+    let book = process_book(book, &mk_config("wikilinks = true")).unwrap();
+    let content = chapter_content(&book, "ch2.md");
+    assert_eq!(content.matches("[ch1](ch1.md)").count(), 1, "{content}");
+}
+
+#[test]
+fn test_wikilinks_in_code_blocks() {
+    // Wikilinks written in fenced or indented code blocks are examples, not links.
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "ch1.md", "```\n[[ch3]]\n```\n", vec![1]),
+        mk_chap("ch2", "ch2.md", "Example:\n\n    [[ch3]]\n", vec![2]),
+        mk_chap("ch3", "ch3.md", "", vec![3]),
+    ]);
+    let book = process_book(book, &mk_config("wikilinks = true")).unwrap();
+    assert_eq!(chapter_content(&book, "ch3.md"), "");
+}
+
+#[test]
+fn test_html_links() {
+    assert_eq!(
+        html_hrefs(r#"<a href="x.md">x</a> <A class=c HREF='y.md'> <abbr href="z"> <a href=w.md>"#),
+        vec!["x.md", "y.md", "w.md"]
+    );
+
+    let mk_book = || {
+        Book::new_with_items(vec![
+            mk_chap(
+                "ch1",
+                "a/ch1.md",
+                r#"<a href="../b/last_chapter.md">x</a>"#,
+                vec![1],
+            ),
+            mk_chap(
+                "ch2",
+                "b/ch2.md",
+                "<div>\n<a href=\"last_chapter.md#intro\">x</a>\n</div>",
+                vec![2],
+            ),
+            mk_chap(
+                "ch3",
+                "b/ch3.md",
+                r#"<a href="https://b/last_chapter.md">"#,
+                vec![3],
+            ),
+            mk_chap("last_chapter", "b/last_chapter.md", "", vec![4]),
+        ])
+    };
+    let book = process_book(mk_book(), &Config::default()).unwrap();
+    assert_eq!(chapter_content(&book, "b/last_chapter.md"), "");
+
+    let book = process_book(mk_book(), &mk_config("html_links = true")).unwrap();
+    let content = chapter_content(&book, "b/last_chapter.md");
+    assert!(content.contains("* [ch1](../a/ch1.md)"), "{content}");
+    assert!(content.contains("* [ch2 → intro](ch2.md)"), "{content}");
+    assert!(!content.contains("ch3"), "{content}");
+}
+
+#[test]
+fn test_self_links() {
+    let mk_book = || {
+        Book::new_with_items(vec![
+            mk_chap(
+                "ch1",
+                "a/ch1.md",
+                "[x](ch1.md) [y](../a/ch1.md#top)",
+                vec![1],
+            ),
+            mk_chap("ch2", "a/ch2.md", "[x](ch1.md)", vec![2]),
+        ])
+    };
+    let book = process_book(mk_book(), &Config::default()).unwrap();
+    let content = chapter_content(&book, "a/ch1.md");
+    assert!(content.contains("* [ch2](ch2.md)"), "{content}");
+    assert!(!content.contains("* [ch1"), "{content}");
+
+    let book = process_book(mk_book(), &mk_config("include_self = true")).unwrap();
+    let content = chapter_content(&book, "a/ch1.md");
+    assert!(content.contains("* [ch1](ch1.md)"), "{content}");
+    assert!(content.contains("* [ch1 → top](ch1.md)"), "{content}");
+}
+
+#[test]
+fn test_same_page_links() {
+    let mk_book = || {
+        Book::new_with_items(vec![
+            mk_chap("ch1", "a/ch1.md", "[x](#usage)", vec![1]),
+            mk_chap("ch2", "a/ch2.md", "[x](ch1.md)", vec![2]),
+        ])
+    };
+    let book = process_book(mk_book(), &mk_config("include_self = true")).unwrap();
+    let content = chapter_content(&book, "a/ch1.md");
+    assert!(!content.contains("* [ch1"), "{content}");
+
+    let config = mk_config("include_self = true\nsame_page_links = true");
+    let book = process_book(mk_book(), &config).unwrap();
+    let content = chapter_content(&book, "a/ch1.md");
+    assert!(content.contains("* [ch1 → usage](ch1.md)"), "{content}");
+    assert!(content.contains("* [ch2](ch2.md)"), "{content}");
+}
+
+#[test]
+fn test_title_source() {
+    let mk_book = || {
+        Book::new_with_items(vec![
+            mk_chap(
+                "ch1",
+                "ch1.md",
+                "Intro\n\n# The `First` Chapter\n\n[x](ch3.md)",
+                vec![1],
+            ),
+            mk_chap("ch2", "ch2.md", "## Not a title\n\n[x](ch3.md)", vec![2]),
+            mk_chap("ch3", "ch3.md", "", vec![3]),
+        ])
+    };
+    let book = process_book(mk_book(), &mk_config(r#"title_source = "heading""#)).unwrap();
+    let content = chapter_content(&book, "ch3.md");
+    assert!(
+        content.contains("* [The First Chapter](ch1.md)"),
+        "{content}"
+    );
+    assert!(content.contains("* [ch2](ch2.md)"), "{content}");
+}
+
+#[test]
+fn test_breadcrumb() {
+    let mut setup = Chapter::new("Setup", String::new(), "setup/index.md", vec![]);
+    let install = Chapter::new(
+        "Install",
+        "[x](../ref.md)".into(),
+        "setup/install.md",
+        vec!["Setup".into()],
+    );
+    setup.sub_items.push(BookItem::Chapter(install));
+    let book = Book::new_with_items(vec![
+        mk_chap("Intro", "intro.md", "[x](ref.md)", vec![1]),
+        BookItem::PartTitle("Guide".into()),
+        BookItem::Chapter(setup),
+        mk_chap("Reference", "ref.md", "", vec![3]),
+    ]);
+    let book = process_book(book, &mk_config("breadcrumb = true")).unwrap();
+    let content = chapter_content(&book, "ref.md");
+    assert!(content.contains("* [Intro](intro.md)"), "{content}");
+    assert!(
+        content.contains("* [Guide > Setup > Install](setup/install.md)"),
+        "{content}"
+    );
+}
+
+#[test]
+fn test_group_by_part() {
+    let mut draft = Chapter::new("draft", "[x](target.md)".into(), "draft.md", vec![]);
+    draft.number = None;
+    let book = Book::new_with_items(vec![
+        mk_chap("Part One", "p1.md", "", vec![1]),
+        mk_chap("ch1.2", "ch1.2.md", "[x](target.md)", vec![1, 2]),
+        mk_chap("ch1.1", "ch1.1.md", "[x](target.md)", vec![1, 1]),
+        mk_chap("ch3.1", "ch3.1.md", "[x](target.md)", vec![3, 1]),
+        BookItem::Chapter(draft),
+        mk_chap("target", "target.md", "", vec![2]),
+    ]);
+    let book = process_book(book, &mk_config("group_by_part = true")).unwrap();
+    let expected = [
+        " > * Part One",
+        " >   * [ch1.1](ch1.1.md)",
+        " >   * [ch1.2](ch1.2.md)",
+        " > * Part 3",
+        " >   * [ch3.1](ch3.1.md)",
+        " > * Ungrouped",
+        " >   * [draft](draft.md)",
+    ]
+    .join("\n");
+    let content = chapter_content(&book, "target.md");
+    assert!(content.contains(&expected), "{content}");
+}
+
+#[test]
+fn test_collapsible() {
+    let mk_book = || {
+        Book::new_with_items(vec![
+            mk_chap("ch1", "ch1.md", "[x](ch2.md)", vec![1]),
+            mk_chap("ch2", "ch2.md", "", vec![2]),
+        ])
+    };
+    let mut config = mk_config(
+        r#"
+        collapsible = true
+        renderers = ["html", "markdown"]
+        "#,
+    );
+    let book = process_book(mk_book(), &config).unwrap();
+    let expected = [
+        " > <details>",
+        " > <summary>Backlinks (1)</summary>",
+        " > ",
+        " > ",
+        " > * [ch1](ch1.md)",
+        " > </details>",
+    ]
+    .join("\n");
+    let content = chapter_content(&book, "ch2.md");
+    assert!(content.contains(&expected), "{content}");
+
+    config.renderer = "markdown".into();
+    let book = process_book(mk_book(), &config).unwrap();
+    let content = chapter_content(&book, "ch2.md");
+    assert!(content.contains("#### Backlinks"), "{content}");
+    assert!(!content.contains("<details>"), "{content}");
+
+    let config = mk_config("collapsible = true\nheading = \"\"");
+    let book = process_book(mk_book(), &config).unwrap();
+    let content = chapter_content(&book, "ch2.md");
+    assert!(content.contains(" > <summary>1</summary>\n"), "{content}");
+}
+
+#[test]
+fn test_max_backlinks() {
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "ch1.md", "[x](target.md)", vec![1]),
+        mk_chap("ch2", "ch2.md", "[x](target.md)", vec![2]),
+        mk_chap("ch3", "ch3.md", "[x](target.md)", vec![3]),
+        mk_chap("target", "target.md", "", vec![4]),
+    ]);
+    let config = mk_config("max_backlinks = 2\nshow_count = true");
+    let book = process_book(book, &config).unwrap();
+    let expected = [
+        " > #### Backlinks (3) { #backlinks }",
+        " > ",
+        " > * [ch1](ch1.md)",
+        " > * [ch2](ch2.md)",
+        " > * …and 1 more",
+    ]
+    .join("\n");
+    let content = chapter_content(&book, "target.md");
+    assert!(content.contains(&expected), "{content}");
+}
+
+#[test]
+fn test_orphans() {
+    let book = Book::new_with_items(vec![
+        mk_chap("index", "index.md", "[x](a/ch1.md)", vec![0]),
+        mk_chap("ch1", "a/ch1.md", "[x](ch1.md)", vec![1]),
+        mk_chap("ch2", "a/ch2.md", "", vec![2]),
+    ]);
+    let config = Config::default();
+    let graph = LinkGraph::collect(&book, &config).unwrap();
+    assert_eq!(
+        graph.orphans(&config).unwrap(),
+        [Path::new("a/ch2.md"), Path::new("index.md")]
+    );
+    let config = mk_config(r#"orphan_ignore = ["index.md"]"#);
+    assert_eq!(graph.orphans(&config).unwrap(), [Path::new("a/ch2.md")]);
+}
+
+#[test]
+fn test_fail_on_orphans() {
+    let book = Book::new_with_items(vec![
+        mk_chap("index", "index.md", "[x](a/ch1.md)", vec![0]),
+        mk_chap("ch1", "a/ch1.md", "", vec![1]),
+        mk_chap("ch2", "a/ch2.md", "", vec![2]),
+    ]);
+    let err = process_book(book.clone(), &mk_config("fail_on_orphans = true")).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "found 2 chapter(s) that no chapter links to: a/ch2.md, index.md. Link to them, or list \
+         them in `orphan_ignore`"
+    );
+    let config = mk_config("fail_on_orphans = true\norphan_ignore = [\"index.md\", \"a/ch2.md\"]");
+    assert!(process_book(book.clone(), &config).is_ok());
+
+    // Only preprocessing fails: report-only mode and the `check` subcommand report the orphans.
+    let config = mk_config("fail_on_orphans = true\nreport_only = true");
+    assert!(process_book(book.clone(), &config).is_ok());
+    let book_config = "[preprocessor.backlinks]\nfail_on_orphans = true"
+        .parse()
+        .unwrap();
+    let ctx = PreprocessorContext::new(PathBuf::new(), book_config, "html".into());
+    assert!(check_links(&ctx, book).is_ok());
+}
+
+#[test]
+fn test_scope() {
+    let mut appendix = Chapter::new("appendix", "[x](b/ch2.md)".into(), "appendix.md", vec![]);
+    appendix.number = None;
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "a/ch1.md", "[x](../b/ch2.md) [x](ch1.1.md)", vec![1]),
+        mk_chap("ch1.1", "a/ch1.1.md", "[x](ch1.md)", vec![1, 1]),
+        mk_chap("ch2", "b/ch2.md", "[x](../a/ch1.md)", vec![2]),
+        BookItem::Chapter(appendix),
+    ]);
+    let backlinks = |graph: &LinkGraph, path: &str| {
+        graph.backlinks[&Path::new(path).normalize_path().unwrap()]
+            .iter()
+            .map(|b| b.source.name.as_str())
+            .sorted()
+            .join(" ")
+    };
+    let graph = LinkGraph::collect(&book, &Config::default()).unwrap();
+    assert_eq!(backlinks(&graph, "a/ch1.md"), "ch1.1 ch2");
+    assert_eq!(backlinks(&graph, "b/ch2.md"), "appendix ch1");
+
+    let graph = LinkGraph::collect(&book, &mk_config(r#"scope = "part""#)).unwrap();
+    assert_eq!(backlinks(&graph, "a/ch1.md"), "ch1.1");
+    assert_eq!(backlinks(&graph, "a/ch1.1.md"), "ch1");
+    assert_eq!(backlinks(&graph, "b/ch2.md"), "");
+}
+
+#[test]
+fn test_hubs() {
+    let book = Book::new_with_items(vec![
+        mk_chap("a", "a.md", "[x](c.md) [y](c.md) [z](b.md)", vec![1]),
+        mk_chap("b", "b.md", "[x](c.md)", vec![2]),
+        mk_chap("c", "c.md", "[x](a.md)", vec![3]),
+    ]);
+    let graph = LinkGraph::collect(&book, &Config::default()).unwrap();
+    let hubs = graph
+        .hubs(2, Dedup::Full)
+        .into_iter()
+        .map(|(info, count)| (info.name.as_str(), count))
+        .collect_vec();
+    assert_eq!(hubs, [("c", 2), ("a", 1)]);
+}
+
+#[test]
+fn test_supports_renderer() {
+    let config = Config::default();
+    assert!(config.supports_renderer("html"));
+    assert!(!config.supports_renderer("markdown"));
+    let config = mk_config(r#"renderers = ["html", "markdown"]"#);
+    assert!(config.supports_renderer("markdown"));
+    assert!(!config.supports_renderer("epub"));
+}
+
+#[test]
+fn test_renderers() {
+    let mk_book = || {
+        Book::new_with_items(vec![
+            mk_chap("ch1", "ch1.md", "[x](ch2.md)", vec![1]),
+            mk_chap("ch2", "ch2.md", "", vec![2]),
+        ])
+    };
+    let config = Config {
+        renderer: "markdown".into(),
+        ..Config::default()
+    };
+    let book = process_book(mk_book(), &config).unwrap();
+    assert_eq!(chapter_content(&book, "ch2.md"), "");
+
+    let mut config = mk_config(r#"renderers = ["html", "markdown"]"#);
+    config.renderer = "markdown".into();
+    let book = process_book(mk_book(), &config).unwrap();
+    assert!(chapter_content(&book, "ch2.md").contains("[ch1](ch1.md)"));
+}
+
+#[test]
+fn test_separator() {
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "ch1.md", "[x](ch2.md)", vec![1]),
+        mk_chap("ch2", "ch2.md", "Text", vec![2]),
+    ]);
+    let config = mk_config("separator = false");
+    let book = process_book(book, &config).unwrap();
+    let expected = [
+        "Text",
+        "",
+        "<!-- backlinks -->",
+        "",
+        "<div class=\"mdbook-backlinks\">",
+        "",
+        " > ",
+        " > #### Backlinks { #backlinks }",
+        " > ",
+        " > * [ch1](ch1.md)",
+        "",
+        "</div>",
+        "",
+        "<!-- /backlinks -->",
+    ]
+    .join("\n");
+    assert_eq!(chapter_content(&book, "ch2.md"), expected);
+}
+
+#[test]
+fn test_localized_headings() {
+    let book = Book::new_with_items(vec![
+        mk_chap(
+            "en",
+            "en/ch1.md",
+            "[x](../fr/ch1.md) [x](../ch1.md)",
+            vec![1],
+        ),
+        mk_chap("fr", "fr/ch1.md", "[x](../en/ch1.md)", vec![2]),
+        mk_chap("root", "ch1.md", "[x](en/ch1.md)", vec![3]),
+    ]);
+    let config = mk_config(
+        r#"
+        heading = "Default"
+        headings = { en = "Backlinks", fr = "Rétroliens" }
+        "#,
+    );
+    let book = process_book(book, &config).unwrap();
+    assert!(chapter_content(&book, "en/ch1.md").contains("#### Backlinks"));
+    assert!(chapter_content(&book, "fr/ch1.md").contains("#### Rétroliens"));
+    assert!(chapter_content(&book, "ch1.md").contains("#### Default"));
+}
+
+#[test]
+fn test_css_class() {
+    let mk_book = || {
+        Book::new_with_items(vec![
+            mk_chap("ch1", "ch1.md", "[x](ch2.md)", vec![1]),
+            mk_chap("ch2", "ch2.md", "", vec![2]),
+        ])
+    };
+    let config = Config::default();
+    let book = process_book(mk_book(), &config).unwrap();
+    let expected = [
+        "<div class=\"mdbook-backlinks\">",
+        "",
+        "---",
+        "",
+        " > ",
+        " > #### Backlinks { #backlinks }",
+        " > ",
+        " > * [ch1](ch1.md)",
+        "",
+        "</div>",
+    ]
+    .join("\n");
+    let content = chapter_content(&book, "ch2.md");
+    assert!(content.contains(&expected), "{content}");
+
+    let config = mk_config(r#"css_class = """#);
+    let book = process_book(mk_book(), &config).unwrap();
+    let content = chapter_content(&book, "ch2.md");
+    assert!(!content.contains("<div"), "{content}");
+}
+
+#[test]
+fn test_placeholder() {
+    let mk_book = || {
+        Book::new_with_items(vec![
+            mk_chap("ch1", "ch1.md", "[x](ch2.md)", vec![1]),
+            mk_chap("ch2", "ch2.md", "Text\n\n{{#backlinks}}\n\nFooter", vec![2]),
+            mk_chap("ch3", "ch3.md", "[x](ch1.md)\n\n{{#backlinks}}", vec![3]),
+        ])
+    };
+    let config = mk_config(r#"css_class = """#);
+    let book = process_book(mk_book(), &config).unwrap();
+    let expected = [
+        "Text",
+        "",
+        "<!-- backlinks placeholder -->",
+        "",
+        "---",
+        "",
+        " > ",
+        " > #### Backlinks { #backlinks }",
+        " > ",
+        " > * [ch1](ch1.md)",
+        "",
+        "<!-- /backlinks -->",
+        "",
+        "Footer",
+    ]
+    .join("\n");
+    assert_eq!(chapter_content(&book, "ch2.md"), expected);
+    // Chapters without backlinks lose the placeholder.
+    assert!(!chapter_content(&book, "ch3.md").contains("{{#backlinks}}"));
+    // Chapters without placeholder still get the block at the end...
+    assert!(chapter_content(&book, "ch1.md").ends_with("<!-- /backlinks -->"));
+    // ... but not with `placeholder_only`.
+    let config = mk_config("placeholder_only = true");
+    let book = process_book(mk_book(), &config).unwrap();
+    assert_eq!(chapter_content(&book, "ch1.md"), "[x](ch2.md)");
+
+    // Running twice gives the same result.
+    let once = process_book(mk_book(), &config).unwrap();
+    let twice = process_book(once.clone(), &config).unwrap();
+    assert_eq!(
+        chapter_content(&once, "ch2.md"),
+        chapter_content(&twice, "ch2.md")
+    );
+}
+
+#[test]
+fn test_placeholder_in_code() {
+    // Placeholders in code spans and code blocks document the syntax, and are left as is.
+    let content =
+        "Write `{{#backlinks}}` to place it:\n\n```\n{{#backlinks}}\n```\n\n{{#backlinks}}";
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "ch1.md", "[x](ch2.md)", vec![1]),
+        mk_chap("ch2", "ch2.md", content, vec![2]),
+    ]);
+    let book = process_book(book, &Config::default()).unwrap();
+    let content = chapter_content(&book, "ch2.md");
+    assert!(
+        content.starts_with(
+            "Write `{{#backlinks}}` to place it:\n\n```\n{{#backlinks}}\n```\n\n\
+             <!-- backlinks placeholder -->"
+        ),
+        "{content}"
+    );
+    assert_eq!(content.matches("<!-- backlinks placeholder -->").count(), 1);
+}
+
+#[test]
+fn test_opt_out() {
+    let book = Book::new_with_items(vec![
+        mk_chap(
+            "ch1",
+            "ch1.md",
+            "[x](ch2.md) [x](ch3.md) [x](ch4.md) [x](ch5.md)",
+            vec![1],
+        ),
+        mk_chap("ch2", "ch2.md", "<!-- no-backlinks -->\nText", vec![2]),
+        mk_chap(
+            "ch3",
+            "ch3.md",
+            "---\ntitle: x\nbacklinks: false\n---\nText",
+            vec![3],
+        ),
+        mk_chap("ch4", "ch4.md", "---\nbacklinks: true\n---\nText", vec![4]),
+        mk_chap(
+            "ch5",
+            "ch5.md",
+            "<!-- no-backlinks -->\nText\n\n{{#backlinks}}",
+            vec![5],
+        ),
+    ]);
+    let book = process_book(book, &Config::default()).unwrap();
+    assert_eq!(
+        chapter_content(&book, "ch2.md"),
+        "<!-- no-backlinks -->\nText"
+    );
+    assert_eq!(
+        chapter_content(&book, "ch3.md"),
+        "---\ntitle: x\nbacklinks: false\n---\nText"
+    );
+    assert!(chapter_content(&book, "ch4.md").contains("[ch1](ch1.md)"));
+    // Chapters that opted out lose their placeholder.
+    assert_eq!(
+        chapter_content(&book, "ch5.md"),
+        "<!-- no-backlinks -->\nText\n\n"
+    );
+}
+
+#[test]
+fn test_index_names() {
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "ch1.md", "[x](a/) [y](b) [z](c/)", vec![1]),
+        mk_chap("a", "a/README.md", "", vec![2]),
+        mk_chap("b", "b/index.md", "", vec![3]),
+        mk_chap("c", "c/intro.md", "", vec![4]),
+    ]);
+    let graph = LinkGraph::collect(&book, &Config::default()).unwrap();
+    let backlinks = |path: &str| graph.backlinks[&Path::new(path).normalize_path().unwrap()].len();
+    assert_eq!(backlinks("a/README.md"), 1);
+    assert_eq!(backlinks("b/index.md"), 1);
+    assert_eq!(backlinks("c/intro.md"), 0);
+
+    let config = mk_config(r#"index_names = ["intro.md"]"#);
+    let graph = LinkGraph::collect(&book, &config).unwrap();
+    let backlinks = |path: &str| graph.backlinks[&Path::new(path).normalize_path().unwrap()].len();
+    assert_eq!(backlinks("a/README.md"), 0);
+    assert_eq!(backlinks("c/intro.md"), 1);
+}
+
+#[test]
+fn test_equivalent_targets() {
+    let book = Book::new_with_items(vec![
+        mk_chap(
+            "ch1",
+            "a/ch1.md",
+            "[x](../b/last_chapter.md) [x](./../b/last_chapter.md) [x](../a/../b/last_chapter.md) \
+             [x](..//b/./last_chapter.md) [x](../c) [x](../c/) [x](./../c/.) [x](../b/../c//)",
+            vec![1],
+        ),
+        mk_chap("last_chapter", "b/last_chapter.md", "", vec![2]),
+        mk_chap("c", "./c/index.md", "", vec![3]),
+    ]);
+    let config = mk_config("check_broken = true\nfail_on_broken = true");
+    let graph = LinkGraph::collect(&book, &config).unwrap();
+    let backlinks = |path: &str| graph.backlinks[&Path::new(path).normalize_path().unwrap()].len();
+    assert_eq!(backlinks("b/last_chapter.md"), 4);
+    assert_eq!(backlinks("c/index.md"), 4);
+}
+
+#[test]
+fn test_duplicate_source_paths() {
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "ch1.md", "[x](ch2.md)", vec![1]),
+        mk_chap("ch2", "ch2.md", "", vec![2]),
+        mk_chap("copy", "./ch2.md", "", vec![3]),
+    ]);
+    let err = process_book(book, &Config::default()).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "chapters `ch2` and `copy` have the same source path `ch2.md`"
+    );
+}
+
+#[test]
+fn test_escaping_links() {
+    let book = Book::new_with_items(vec![
+        mk_chap(
+            "ch1",
+            "a/ch1.md",
+            "[x](../../../../../etc/passwd) [y](../b/ch2.md)",
+            vec![1],
+        ),
+        mk_chap("ch2", "b/ch2.md", "", vec![2]),
+    ]);
+    let book = process_book(book, &mk_config("warn_escapes = true")).unwrap();
+    let content = chapter_content(&book, "b/ch2.md");
+    assert!(content.contains("* [ch1](../a/ch1.md)"), "{content}");
+
+    // Links above the source directory are dropped, absolute ones are kept, and we warn about
+    // both.
+    let path = Path::new("a/ch1.md").normalize_path().unwrap();
+    let content = "[x](../../etc/passwd) [y](/etc/passwd) [z](../b/ch2.md)";
+    let links = chapter_links(content, &path, &mk_config("warn_escapes = true")).unwrap();
+    let urls = links
+        .iter()
+        .map(|link| link.dest_url.as_str())
+        .collect_vec();
+    assert_eq!(urls, ["/etc/passwd", "../b/ch2.md"]);
+    assert_eq!(
+        escape_warning(&path, "../../etc/passwd", None).as_deref(),
+        Some(
+            "Warning: a/ch1.md links to `../../etc/passwd`, which is outside of the book's \
+             source directory"
+        )
+    );
+    let dest = |dest: &str| Path::new(dest).normalize_path().unwrap();
+    assert!(escape_warning(&path, "/etc/passwd", Some(&dest("/etc/passwd"))).is_some());
+    assert!(escape_warning(&path, "../b/ch2.md", Some(&dest("b/ch2.md"))).is_none());
+}
+
+#[test]
+fn test_empty_links() {
+    let path = Path::new("ch1.md").normalize_path().unwrap();
+    let config = mk_config("same_page_links = true\nwarn_empty_links = true");
+    let content = "[x]() [y](<>) [z][undefined] <a href=\"\">w</a> [v](#usage)";
+    let links = chapter_links(content, &path, &config).unwrap();
+    assert_eq!(links.len(), 1);
+    assert_eq!(links[0].dest_url, "#usage");
+}
+
+#[test]
+fn test_percent_decode() {
+    assert_eq!(percent_decode("my%20chapter.md"), "my chapter.md");
+    assert_eq!(percent_decode("caf%C3%A9.md"), "café.md");
+    assert_eq!(percent_decode("100%.md"), "100%.md");
+    assert_eq!(percent_decode("%zz%4"), "%zz%4");
+    assert_eq!(percent_decode("%FF.md"), "%FF.md");
+
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "ch1.md", "[x](my%20chapter.md#a%20b)", vec![1]),
+        mk_chap("ch2", "my chapter.md", "", vec![2]),
+    ]);
+    let graph = LinkGraph::collect(&book, &Config::default()).unwrap();
+    let backlinks = &graph.backlinks[&Path::new("my chapter.md").normalize_path().unwrap()];
+    assert_eq!(backlinks.len(), 1);
+}
+
+#[test]
+fn test_query_strings() {
+    let book = Book::new_with_items(vec![
+        mk_chap(
+            "ch1",
+            "ch1.md",
+            "[x](page.md?x=1#sec) [y](page.md?highlight=foo)",
+            vec![1],
+        ),
+        mk_chap("page", "page.md", "", vec![2]),
+    ]);
+    let graph = LinkGraph::collect(&book, &mk_config("check_broken = true")).unwrap();
+    let backlinks = &graph.backlinks[&Path::new("page.md").normalize_path().unwrap()];
+    let fragments = backlinks
+        .iter()
+        .map(|b| b.fragment.as_deref())
+        .collect_vec();
+    assert_eq!(fragments, [Some("sec"), None]);
+}
+
+#[test]
+fn test_backslash_links() {
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "a/ch1.md", r"[x](..\b\last_chapter.md)", vec![1]),
+        mk_chap("last", "b/last_chapter.md", "", vec![2]),
+    ]);
+    let graph = LinkGraph::collect(&book, &Config::default()).unwrap();
+    let backlinks = &graph.backlinks[&Path::new("b/last_chapter.md").normalize_path().unwrap()];
+    assert_eq!(backlinks.len(), 1);
+}
+
+#[test]
+fn test_case_insensitive() {
+    let mk_book = || {
+        Book::new_with_items(vec![
+            mk_chap("ch1", "a/ch1.md", "[x](../B/Last_Chapter.md)", vec![1]),
+            mk_chap("last", "b/last_chapter.md", "[x](../A/CH1.MD)", vec![2]),
+        ])
+    };
+    let book = process_book(mk_book(), &Config::default()).unwrap();
+    assert_eq!(
+        chapter_content(&book, "b/last_chapter.md"),
+        "[x](../A/CH1.MD)"
+    );
+
+    let config = mk_config("case_insensitive = true");
+    let book = process_book(mk_book(), &config).unwrap();
+    // The backlinks use the actual paths of the chapters.
+    assert!(chapter_content(&book, "b/last_chapter.md").contains("[ch1](../a/ch1.md)"));
+    assert!(chapter_content(&book, "a/ch1.md").contains("[last](../b/last_chapter.md)"));
+}
+
+#[test]
+fn test_disambiguate() {
+    let mk_book = || {
+        Book::new_with_items(vec![
+            mk_chap("Overview", "a/overview.md", "[x](../target.md)", vec![1, 1]),
+            mk_chap("Overview", "b/overview.md", "[x](../target.md)", vec![2, 1]),
+            mk_chap("Other", "other.md", "[x](target.md)", vec![3]),
+            mk_chap("target", "target.md", "", vec![4]),
+        ])
+    };
+    let book = process_book(mk_book(), &mk_config(r#"disambiguate = "number""#)).unwrap();
+    let content = chapter_content(&book, "target.md");
+    assert!(
+        content.contains("[Overview (1.1)](a/overview.md)"),
+        "{content}"
+    );
+    assert!(
+        content.contains("[Overview (2.1)](b/overview.md)"),
+        "{content}"
+    );
+    assert!(content.contains("[Other](other.md)"), "{content}");
+
+    let book = process_book(mk_book(), &mk_config(r#"disambiguate = "path""#)).unwrap();
+    let content = chapter_content(&book, "target.md");
+    assert!(
+        content.contains("[Overview (a/overview.md)](a/overview.md)"),
+        "{content}"
+    );
+
+    let book = process_book(mk_book(), &mk_config(r#"disambiguate = "parent""#)).unwrap();
+    let content = chapter_content(&book, "target.md");
+    assert!(content.contains("[a/Overview](a/overview.md)"), "{content}");
+    assert!(content.contains("[b/Overview](b/overview.md)"), "{content}");
+    assert!(content.contains("[Other](other.md)"), "{content}");
+
+    let book = process_book(mk_book(), &Config::default()).unwrap();
+    let content = chapter_content(&book, "target.md");
+    assert!(content.contains("[Overview](a/overview.md)"), "{content}");
+}
+
+#[test]
+fn test_disambiguate_parent() {
+    // Section numbers already tell numbered chapters apart, but not unnumbered ones.
+    let book = Book::new_with_items(vec![
+        mk_chap("Overview", "a/overview.md", "[x](../target.md)", vec![1, 1]),
+        mk_chap("Overview", "b/overview.md", "[x](../target.md)", vec![2, 1]),
+        mk_chap("Notes", "c/notes.md", "[x](../target.md)", vec![]),
+        mk_chap("Notes", "d/notes.md", "[x](../target.md)", vec![]),
+        mk_chap("target", "target.md", "", vec![3]),
+    ]);
+    let config = mk_config("disambiguate = \"parent\"\nshow_numbers = true");
+    let book = process_book(book, &config).unwrap();
+    let content = chapter_content(&book, "target.md");
+    assert!(
+        content.contains("[1.1 Overview](a/overview.md)"),
+        "{content}"
+    );
+    assert!(
+        content.contains("[2.1 Overview](b/overview.md)"),
+        "{content}"
+    );
+    assert!(content.contains("[c/Notes](c/notes.md)"), "{content}");
+    assert!(content.contains("[d/Notes](d/notes.md)"), "{content}");
+
+    // Chapters at the root of the book have no directory to add.
+    let book = Book::new_with_items(vec![
+        mk_chap("Overview", "overview.md", "[x](target.md)", vec![1]),
+        mk_chap("Overview", "a/overview.md", "[x](../target.md)", vec![2]),
+        mk_chap("target", "target.md", "", vec![3]),
+    ]);
+    let book = process_book(book, &mk_config(r#"disambiguate = "parent""#)).unwrap();
+    let content = chapter_content(&book, "target.md");
+    assert!(content.contains("[Overview](overview.md)"), "{content}");
+    assert!(content.contains("[a/Overview](a/overview.md)"), "{content}");
+}
+
+#[test]
+fn test_show_repeat_count() {
+    let mk_book = || {
+        Book::new_with_items(vec![
+            mk_chap("ch1", "ch1.md", "[x](target.md)", vec![1]),
+            mk_chap(
+                "ch2",
+                "ch2.md",
+                "[x](target.md) [y](target.md) [z](target.md)",
+                vec![2],
+            ),
+            mk_chap("target", "target.md", "", vec![3]),
+        ])
+    };
+    let book = process_book(mk_book(), &mk_config("show_repeat_count = true")).unwrap();
+    let content = chapter_content(&book, "target.md");
+    assert!(content.contains(" > * [ch1](ch1.md)\n"), "{content}");
+    assert!(
+        content.contains(" > * [ch2](ch2.md) (3 links)"),
+        "{content}"
+    );
+
+    let book = process_book(mk_book(), &Config::default()).unwrap();
+    let content = chapter_content(&book, "target.md");
+    assert!(!content.contains("links)"), "{content}");
+}
+
+#[test]
+fn test_template() {
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "a/ch1.md", "[x](../b/ch2.md)", vec![1, 2]),
+        mk_chap("ch2", "b/ch2.md", "", vec![2]),
+    ]);
+    let template = "Linked from {{chapter.name}} ({{chapter.path}}):\n\
+                    {{#each backlinks}}- [{{number}} {{name}}]({{url}})\n{{/each}}";
+    let config = Config {
+        template_source: Some(template.to_owned()),
+        ..Config::default()
+    };
+    let book = process_book(book, &config).unwrap();
+    let content = chapter_content(&book, "b/ch2.md");
+    let expected = [
+        "<!-- backlinks -->",
+        "",
+        "Linked from ch2 (b/ch2.md):",
+        "- [1.2 ch1](../a/ch1.md)",
+        "",
+        "",
+        "<!-- /backlinks -->",
+    ]
+    .join("\n");
+    assert!(content.ends_with(&expected), "{content}");
+}
+
+#[test]
+fn test_template_unnumbered() {
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "ch1.md", "[x](ch3.md)", vec![1]),
+        mk_chap("ch2", "ch2.md", "[x](ch3.md)", vec![]),
+        mk_chap("ch3", "ch3.md", "", vec![3]),
+    ]);
+    // Unnumbered chapters have no `number` field at all.
+    let template = "{{#each backlinks}}{{name}}:{{#each this}} {{@key}}{{/each}}\n{{/each}}";
+    let config = Config {
+        template_source: Some(template.to_owned()),
+        ..Config::default()
+    };
+    let book = process_book(book, &config).unwrap();
+    let content = chapter_content(&book, "ch3.md");
+    assert!(content.contains("ch2: name url\n"), "{content}");
+    assert!(content.contains("ch1: name number url\n"), "{content}");
+}
+
+#[test]
+fn test_collect_backlinks() {
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "a/ch1.md", "[x](../b/ch2.md)", vec![1]),
+        mk_chap("ch2", "b/ch2.md", "", vec![2]),
+    ]);
+    let backlinks = collect_backlinks(&book).unwrap();
+    let ch2 = &backlinks[&Path::new("b/ch2.md").normalize_path().unwrap()];
+    assert_eq!(ch2.len(), 1);
+    assert_eq!(ch2[0].source.name, "ch1");
+    assert!(backlinks[&Path::new("a/ch1.md").normalize_path().unwrap()].is_empty());
+}
+
+#[test]
+fn test_section_number() {
+    assert_eq!(section_number(&[2, 3, 1, 4]), "2.3.1.4");
+    assert_eq!(section_number(&[7]), "7");
+    assert_eq!(section_number(&[]), "");
+}
+
+#[test]
+fn test_show_numbers() {
+    let unnumbered = Chapter::new("draft", "[x](target.md)".into(), "draft.md", vec![]);
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "ch1.md", "[x](target.md)", vec![2, 2]),
+        BookItem::Chapter(unnumbered),
+        mk_chap("target", "target.md", "", vec![3]),
+    ]);
+    let book = process_book(book, &mk_config("show_numbers = true")).unwrap();
+    let content = chapter_content(&book, "target.md");
+    assert!(content.contains(" > * [2.2 ch1](ch1.md)"), "{content}");
+    assert!(content.contains(" > * [draft](draft.md)\n"), "{content}");
+}
+
+#[test]
+fn test_number_separator() {
+    let mut ch2 = Chapter::new(
+        "ch2",
+        "[`x`](../c/ch3.md)".into(),
+        "b/ch2.md",
+        vec!["ch1".into()],
+    );
+    ch2.number = Some(mdbook_preprocessor::book::SectionNumber::new(vec![1, 1]));
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "a/ch1.md", "[x](../c/ch3.md)", vec![1]),
+        BookItem::Chapter(ch2),
+        mk_chap("ch3", "c/ch3.md", "", vec![2]),
+    ]);
+    let config = indoc::indoc!(
+        r#"
+        show_numbers = true
+        number_separator = " — "
+        breadcrumb = true
+        "#
+    );
+    let book = process_book(book.clone(), &mk_config(config)).unwrap();
+    let content = chapter_content(&book, "c/ch3.md");
+    assert!(content.contains("* [1 — ch1](../a/ch1.md)"), "{content}");
+    assert!(
+        content.contains("* [1.1 — ch1 > ch2](../b/ch2.md)"),
+        "{content}"
+    );
+
+    let config = "show_numbers = true\nnumber_separator = \": \"\nlink_text = \"anchor\"";
+    let book = process_book(book, &mk_config(config)).unwrap();
+    let content = chapter_content(&book, "c/ch3.md");
+    assert!(content.contains("* [1.1: `x`](../b/ch2.md)"), "{content}");
+}
+
+#[test]
+fn test_numeric_section_order() {
+    let book = Book::new_with_items(vec![
+        mk_chap("b", "b.md", "[x](target.md)", vec![10, 2]),
+        mk_chap("a", "a.md", "[x](target.md)", vec![9, 1]),
+        mk_chap("c", "c.md", "[x](target.md)", vec![9, 10]),
+        mk_chap("target", "target.md", "", vec![11]),
+    ]);
+    let book = process_book(book, &mk_config("show_numbers = true")).unwrap();
+    let content = chapter_content(&book, "target.md");
+    let expected = [
+        " > * [9.1 a](a.md)",
+        " > * [9.10 c](c.md)",
+        " > * [10.2 b](b.md)",
+    ]
+    .join("\n");
+    assert!(content.contains(&expected), "{content}");
+}
+
+#[test]
+fn test_drafts() {
+    let mut draft = Chapter::new_draft("draft", vec![]);
+    draft.content = "[x](ch2.md)".into();
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "ch1.md", "[x](draft.md)", vec![1]),
+        mk_chap("ch2", "ch2.md", "", vec![2]),
+        BookItem::Chapter(draft),
+        BookItem::Chapter(Chapter::new_draft("empty draft", vec![])),
+    ]);
+    assert_eq!(drafts_with_links(&book), ["draft"]);
+    // Links to drafts are broken, and links from drafts are ignored.
+    let result = LinkGraph::collect(&book, &mk_config("fail_on_broken = true"));
+    assert!(result.is_err());
+    let graph = LinkGraph::collect(&book, &Config::default()).unwrap();
+    assert!(
+        graph
+            .backlinks
+            .values()
+            .all(|backlinks| backlinks.is_empty())
+    );
+}
+
+#[test]
+fn test_resolve_includes() {
+    let dir =
+        std::env::temp_dir().join(format!("mdbook-backlinks-includes-{}", std::process::id()));
+    std::fs::create_dir_all(dir.join("a/parts")).unwrap();
+    std::fs::write(dir.join("a/parts/links.md"), "{{#include more.md:2:3}}").unwrap();
+    // Links are relative to the including chapter, since that is where they end up.
+    std::fs::write(dir.join("a/parts/more.md"), "[x](ch2.md)").unwrap();
+    let mk_book = || {
+        Book::new_with_items(vec![
+            mk_chap("ch1", "a/ch1.md", "{{#include parts/links.md}}", vec![1]),
+            mk_chap("ch2", "a/ch2.md", "{{#include missing.md}}", vec![2]),
+        ])
+    };
+    let ch2 = Path::new("a/ch2.md").normalize_path().unwrap();
+
+    let graph = LinkGraph::collect(&mk_book(), &Config::default()).unwrap();
+    assert!(graph.backlinks[&ch2].is_empty());
+
+    let config = Config {
+        src_dir: dir.clone(),
+        ..mk_config("resolve_includes = true")
+    };
+    let graph = LinkGraph::collect(&mk_book(), &config).unwrap();
+    assert_eq!(graph.backlinks[&ch2].len(), 1);
+    assert_eq!(graph.backlinks[&ch2][0].source.name, "ch1");
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_related() {
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "ch1.md", "[x](t1.md) [x](t2.md) [x](t3.md)", vec![1]),
+        mk_chap("ch2", "ch2.md", "[x](t1.md) [x](t2.md)", vec![2]),
+        mk_chap("ch3", "ch3.md", "[x](t3.md) [x](t4.md)", vec![3]),
+        mk_chap("ch4", "ch4.md", "[x](t4.md)", vec![4]),
+        mk_chap("t1", "t1.md", "", vec![5]),
+        mk_chap("t2", "t2.md", "", vec![6]),
+        mk_chap("t3", "t3.md", "", vec![7]),
+        mk_chap("t4", "t4.md", "", vec![8]),
+    ]);
+    let config = mk_config("related = true\nrelated_count = 1\ncss_class = \"\"");
+    let graph = LinkGraph::collect(&book, &config).unwrap();
+    let related = |path: &str, count| {
+        let key = Path::new(path).normalize_path().unwrap();
+        graph
+            .related(&key, count)
+            .into_iter()
+            .map(|info| info.name.as_str())
+            .collect_vec()
+    };
+    assert_eq!(related("ch1.md", 5), ["ch2", "ch3"]);
+    assert_eq!(related("ch4.md", 5), ["ch3"]);
+    assert!(related("t1.md", 5).is_empty());
+
+    let book = process_book(book, &config).unwrap();
+    let expected = [" > #### Related pages", " > ", " > * [ch2](ch2.md)", ""].join("\n");
+    let content = chapter_content(&book, "ch1.md");
+    assert!(content.contains(&expected), "{content}");
+}
+
+#[test]
+fn test_heading_id() {
+    let mk_book = |content: &str| {
+        Book::new_with_items(vec![
+            mk_chap("ch1", "ch1.md", "[x](ch2.md)", vec![1]),
+            mk_chap("ch2", "ch2.md", content, vec![2]),
+        ])
+    };
+    let book = mk_book("# Backlinks\n\n## Foo {#backlinks-1}");
+    let book = process_book(book, &Config::default()).unwrap();
+    let content = chapter_content(&book, "ch2.md");
+    assert!(
+        content.contains(" > #### Backlinks { #backlinks-2 }"),
+        "{content}"
+    );
+
+    let config = mk_config(r#"heading_id = "refs""#);
+    let book = process_book(mk_book(""), &config).unwrap();
+    let content = chapter_content(&book, "ch2.md");
+    assert!(content.contains(" > #### Backlinks { #refs }"), "{content}");
+
+    let config = mk_config(r#"heading_id = """#);
+    let book = process_book(mk_book(""), &config).unwrap();
+    let content = chapter_content(&book, "ch2.md");
+    assert!(content.contains(" > #### Backlinks\n"), "{content}");
+}
+
+#[test]
+fn test_link_titles() {
+    let mk_book = || {
+        Book::new_with_items(vec![
+            mk_chap("ch1", "ch1.md", r#"[x](last.md "Final chapter")"#, vec![1]),
+            mk_chap("ch2", "ch2.md", "[x](last.md)", vec![2]),
+            mk_chap("last", "last.md", "", vec![3]),
+        ])
+    };
+    let book = process_book(mk_book(), &mk_config("link_titles = true")).unwrap();
+    let content = chapter_content(&book, "last.md");
+    assert!(
+        content.contains(r#" > * [ch1](ch1.md "Final chapter")"#),
+        "{content}"
+    );
+    assert!(content.contains(" > * [ch2](ch2.md)\n"), "{content}");
+
+    let book = process_book(mk_book(), &Config::default()).unwrap();
+    let content = chapter_content(&book, "last.md");
+    assert!(content.contains(" > * [ch1](ch1.md)\n"), "{content}");
+}
+
+#[test]
+fn test_count_images() {
+    let book = Book::new_with_items(vec![
+        mk_chap(
+            "ch1",
+            "ch1.md",
+            "![diagram](figures.md) ![photo](photo.jpg)",
+            vec![1],
+        ),
+        mk_chap("ch2", "ch2.md", "[![icon](icon.png)](figures.md)", vec![2]),
+        mk_chap("figures", "figures.md", "", vec![3]),
+    ]);
+    let figures = Path::new("figures.md").normalize_path().unwrap();
+    let graph = LinkGraph::collect(&book, &Config::default()).unwrap();
+    let sources = graph.backlinks[&figures]
+        .iter()
+        .map(|b| b.source.name.as_str());
+    assert_eq!(sources.collect_vec(), ["ch2"]);
+
+    let config = mk_config("count_images = true\nlink_text = \"anchor\"\nfail_on_broken = true");
+    let graph = LinkGraph::collect(&book, &config).unwrap();
+    let backlinks = graph.backlinks[&figures]
+        .iter()
+        .map(|b| (b.source.name.as_str(), b.anchor_text.as_str()))
+        .collect_vec();
+    assert_eq!(backlinks, [("ch1", "diagram"), ("ch2", "icon")]);
+}
+
+#[test]
+fn test_intro_text() {
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "ch1.md", "[x](ch2.md)", vec![1]),
+        mk_chap("ch2", "ch2.md", "", vec![2]),
+    ]);
+    let config = mk_config(r#"intro_text = "The following pages link here:""#);
+    let book = process_book(book, &config).unwrap();
+    let expected = [
+        " > #### Backlinks { #backlinks }",
+        " > ",
+        " > The following pages link here:",
+        " > ",
+        " > * [ch1](ch1.md)",
+    ]
+    .join("\n");
+    let content = chapter_content(&book, "ch2.md");
+    assert!(content.contains(&expected), "{content}");
+}
+
+#[test]
+fn test_dedup() {
+    let mk_book = || {
+        Book::new_with_items(vec![
+            mk_chap(
+                "ch1",
+                "ch1.md",
+                "[x](ch2.md) [x](ch2.md) [x](ch2.md#intro)",
+                vec![1],
+            ),
+            mk_chap("ch2", "ch2.md", "# Intro", vec![2]),
+        ])
+    };
+    let count = |content: &str, entry: &str| content.lines().filter(|l| l.ends_with(entry)).count();
+
+    let book = process_book(mk_book(), &Config::default()).unwrap();
+    let content = chapter_content(&book, "ch2.md");
+    assert_eq!(count(content, "* [ch1](ch1.md)"), 1, "{content}");
+    assert_eq!(count(content, "* [ch1 → Intro](ch1.md)"), 1, "{content}");
+
+    let config = mk_config("dedup = \"path\"\nshow_repeat_count = true");
+    let book = process_book(mk_book(), &config).unwrap();
+    let content = chapter_content(&book, "ch2.md");
+    assert_eq!(count(content, "* [ch1](ch1.md) (3 links)"), 1, "{content}");
+    assert!(!content.contains("Intro]"), "{content}");
+
+    let book = process_book(mk_book(), &mk_config("dedup = \"none\"")).unwrap();
+    let content = chapter_content(&book, "ch2.md");
+    assert_eq!(count(content, "* [ch1](ch1.md)"), 2, "{content}");
+    assert_eq!(count(content, "* [ch1 → Intro](ch1.md)"), 1, "{content}");
+}
+
+#[test]
+fn test_dedup_anchors() {
+    let mk_book = || {
+        Book::new_with_items(vec![
+            mk_chap(
+                "ch1",
+                "ch1.md",
+                "[x](ch2.md) [x](ch2.md#a) [x](ch2.md#b)",
+                vec![1],
+            ),
+            mk_chap("ch2", "ch2.md", "# A\n\n# B", vec![2]),
+            mk_chap("ch3", "ch3.md", "[x](ch2.md#a) [x](ch2.md#a)", vec![3]),
+        ])
+    };
+    let items = |dedup: &str, group_anchors: bool| {
+        let config = format!("dedup = \"{dedup}\"\ngroup_anchors = {group_anchors}");
+        let book = process_book(mk_book(), &mk_config(&config)).unwrap();
+        chapter_content(&book, "ch2.md")
+            .lines()
+            .filter(|line| line.trim_start_matches([' ', '>']).starts_with("* "))
+            .map(str::to_owned)
+            .collect_vec()
+    };
+    let separate = [
+        " > * [ch1](ch1.md)",
+        " > * [ch1 → A](ch1.md)",
+        " > * [ch1 → B](ch1.md)",
+        " > * [ch3 → A](ch3.md)",
+    ];
+    let merged = [" > * [ch1](ch1.md)", " > * [ch3 → A](ch3.md)"];
+    let grouped = [
+        " > * [ch1](ch1.md)",
+        " >   * [A](ch1.md)",
+        " >   * [B](ch1.md)",
+        " > * [ch3 → A](ch3.md)",
+    ];
+    assert_eq!(items("full", false), separate);
+    assert_eq!(items("full", true), grouped);
+    assert_eq!(items("anchor", false), merged);
+    assert_eq!(items("anchor", true), grouped);
+    assert_eq!(items("path", false), merged);
+    assert_eq!(items("path", true), grouped);
+
+    // Links with different texts stay separate with "anchor", but not with "path".
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "ch1.md", "[one](ch2.md#a) [two](ch2.md#b)", vec![1]),
+        mk_chap("ch2", "ch2.md", "# A\n\n# B", vec![2]),
+    ]);
+    let config = mk_config("dedup = \"anchor\"\nlink_text = \"anchor\"");
+    let content =
+        chapter_content(&process_book(book.clone(), &config).unwrap(), "ch2.md").to_owned();
+    assert!(
+        content.contains("* [one → A](ch1.md)\n > * [two → B](ch1.md)"),
+        "{content}"
+    );
+    let config = mk_config("dedup = \"path\"\nlink_text = \"anchor\"");
+    let content = chapter_content(&process_book(book, &config).unwrap(), "ch2.md").to_owned();
+    assert!(content.contains("* [one](ch1.md)\n"), "{content}");
+}
+
+#[test]
+fn test_mark_mutual() {
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "a/ch1.md", "[x](../b/ch3.md)", vec![1]),
+        mk_chap("ch2", "b/ch2.md", "[x](ch3.md) [x](ch3.md)", vec![2]),
+        mk_chap("ch3", "b/ch3.md", "[x](ch2.md)", vec![3]),
+    ]);
+    let config = mk_config("mark_mutual = true\nshow_repeat_count = true");
+    let book = process_book(book, &config).unwrap();
+    let content = chapter_content(&book, "b/ch3.md");
+    assert!(content.contains("* [ch1](../a/ch1.md)\n"), "{content}");
+    assert!(content.contains("* [ch2](ch2.md) ↔ (2 links)"), "{content}");
+    let content = chapter_content(&book, "b/ch2.md");
+    assert!(content.contains("* [ch3](ch3.md) ↔"), "{content}");
+}
+
+#[test]
+fn test_trailing_definitions() {
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "ch1.md", "See [ch2][ref].\n\n[ref]: ch2.md", vec![1]),
+        mk_chap(
+            "ch2",
+            "ch2.md",
+            "[x](ch1.md)\n\n[unused]: ch3.md\n",
+            vec![2],
+        ),
+    ]);
+    let book = process_book(book, &Config::default()).unwrap();
+    let content = chapter_content(&book, "ch1.md").to_owned();
+    assert!(
+        content.starts_with("See [ch2][ref].\n\n<!-- backlinks -->"),
+        "{content}"
+    );
+    assert!(
+        content.ends_with("<!-- /backlinks -->\n\n[ref]: ch2.md"),
+        "{content}"
+    );
+    // The reference still resolves.
+    let path = Path::new("ch1.md").normalize_path().unwrap();
+    let links = chapter_links(&content, &path, &Config::default()).unwrap();
+    assert!(links.iter().any(|link| link.dest_url == "ch2.md"));
+    let content2 = chapter_content(&book, "ch2.md").to_owned();
+    assert!(
+        content2.ends_with("<!-- /backlinks -->\n\n[unused]: ch3.md\n"),
+        "{content2}"
+    );
+
+    // Running again gives the same result.
+    let book = process_book(book, &Config::default()).unwrap();
+    assert_eq!(chapter_content(&book, "ch1.md"), content);
+    assert_eq!(chapter_content(&book, "ch2.md"), content2);
+}
+
+#[test]
+fn test_root_relative() {
+    let mk_book = || {
+        Book::new_with_items(vec![
+            mk_chap("ch1", "a/ch1.md", "[x](/b/last_chapter.md#end)", vec![1]),
+            mk_chap("last_chapter", "b/last_chapter.md", "", vec![2]),
+        ])
+    };
+    let book = process_book(mk_book(), &Config::default()).unwrap();
+    let content = chapter_content(&book, "b/last_chapter.md");
+    assert!(!content.contains("ch1"), "{content}");
+
+    let book = process_book(mk_book(), &mk_config("root_relative = true")).unwrap();
+    let content = chapter_content(&book, "b/last_chapter.md");
+    assert!(content.contains("* [ch1 → end](../a/ch1.md)"), "{content}");
+}
+
+#[test]
+fn test_list_style() {
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "ch1.md", "[x](ch3.md)", vec![1]),
+        mk_chap("ch2", "ch2.md", "[x](ch3.md)", vec![2]),
+        mk_chap("ch3", "ch3.md", "", vec![3]),
+    ]);
+    let book = process_book(book, &mk_config(r#"list_style = "numbered""#)).unwrap();
+    let content = chapter_content(&book, "ch3.md");
+    let expected = [" > 1. [ch1](ch1.md)", " > 1. [ch2](ch2.md)"].join("\n");
+    assert!(content.contains(&expected), "{content}");
+}
+
+#[test]
+fn test_callout() {
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "ch1.md", "[x](ch2.md)", vec![1]),
+        mk_chap("ch2", "ch2.md", "", vec![2]),
+    ]);
+    let book = process_book(book, &mk_config(r#"callout = "tip""#)).unwrap();
+    let content = chapter_content(&book, "ch2.md").to_owned();
+    assert!(
+        content.contains(" > [!TIP]\n > #### Backlinks"),
+        "{content}"
+    );
+
+    // The block is found again on the next run.
+    let book = process_book(book, &mk_config(r#"callout = "tip""#)).unwrap();
+    assert_eq!(chapter_content(&book, "ch2.md"), content);
+}
+
+#[test]
+fn test_layout_table() {
+    let mut draft = Chapter::new("draft", "[x](ch3.md)".into(), "draft.md", vec![]);
+    draft.number = None;
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "ch1.md", "[x](ch3.md#end)", vec![1, 2]),
+        BookItem::Chapter(draft),
+        mk_chap("ch3", "ch3.md", "# End", vec![3]),
+    ]);
+    let book = process_book(book, &mk_config(r#"layout = "table""#)).unwrap();
+    let content = chapter_content(&book, "ch3.md");
+    let expected = [
+        " > |Page|Section|",
+        " > |----|-------|",
+        " > |[draft](draft.md)||",
+        " > |[ch1 → End](ch1.md)|1.2|",
+    ]
+    .join("\n");
+    assert!(content.contains(&expected), "{content}");
+    // The table is parsed as one.
+    let events = mdbook_markdown::new_cmark_parser(content, &Default::default());
+    assert_eq!(
+        events
+            .filter(|event| matches!(event, Event::Start(Tag::TableRow)))
+            .count(),
+        2
+    );
+}
+
+#[test]
+fn test_formatted_anchor_text() {
+    let book = Book::new_with_items(vec![
+        mk_chap(
+            "ch1",
+            "ch1.md",
+            "[`Config` struct](last_chapter.md#fields)",
+            vec![1],
+        ),
+        mk_chap(
+            "ch2",
+            "ch2.md",
+            "[*very* **important**](last_chapter.md)",
+            vec![2],
+        ),
+        mk_chap("last_chapter", "last_chapter.md", "# Fields", vec![3]),
+    ]);
+    let config = mk_config("link_text = \"anchor\"\nshow_numbers = true");
+    let book = process_book(book, &config).unwrap();
+    let content = chapter_content(&book, "last_chapter.md");
+    assert!(
+        content.contains("* [1 `Config` struct → Fields](ch1.md)"),
+        "{content}"
+    );
+    assert!(
+        content.contains("* [2 *very* **important**](ch2.md)"),
+        "{content}"
+    );
+}
+
+#[test]
+fn test_markdown_style() {
+    let mk_book = || {
+        Book::new_with_items(vec![
+            mk_chap("ch1", "ch1.md", "[x](ch2.md)", vec![1]),
+            mk_chap("ch2", "ch2.md", "Text", vec![2]),
+        ])
+    };
+    let mut config = mk_config(
+        r#"
+        markdown_style = "plain"
+        renderers = ["html", "markdown"]
+        "#,
+    );
+    config.renderer = "markdown".to_owned();
+    let book = process_book(mk_book(), &config).unwrap();
+    let expected = indoc::indoc!(
+        "
+        Text
+
+        <!-- backlinks -->
+
+        ## Backlinks
+
+        * [ch1](ch1.md)
+
+        <!-- /backlinks -->"
+    );
+    assert_eq!(chapter_content(&book, "ch2.md"), expected);
+
+    // The HTML output doesn't change.
+    config.renderer = "html".to_owned();
+    let book = process_book(mk_book(), &config).unwrap();
+    assert!(chapter_content(&book, "ch2.md").contains(" > #### Backlinks"));
+}
+
+#[test]
+fn test_exclude() {
+    let book = Book::new_with_items(vec![
+        mk_chap("index", "index.md", "[x](glossary/terms.md)", vec![1]),
+        mk_chap("terms", "glossary/terms.md", "[x](../ch3.md)", vec![2]),
+        mk_chap("ch3", "ch3.md", "[x](index.md)", vec![3]),
+        mk_chap(
+            "more",
+            "glossary/more.md",
+            "[x](terms.md)\n\n{{#backlinks}}",
+            vec![4],
+        ),
+    ]);
+    let config = mk_config(r#"exclude = ["index.md", "glossary/*.md"]"#);
+    let book = process_book(book, &config).unwrap();
+    assert_eq!(chapter_content(&book, "index.md"), "[x](glossary/terms.md)");
+    assert_eq!(
+        chapter_content(&book, "glossary/terms.md"),
+        "[x](../ch3.md)"
+    );
+    // Excluded chapters lose their placeholder.
+    assert_eq!(
+        chapter_content(&book, "glossary/more.md"),
+        "[x](terms.md)\n\n"
+    );
+    // Excluded chapters still count as backlinks.
+    let content = chapter_content(&book, "ch3.md");
+    assert!(
+        content.contains("* [terms](glossary/terms.md)"),
+        "{content}"
+    );
+}
+
+#[test]
+fn test_include() {
+    let book = Book::new_with_items(vec![
+        mk_chap(
+            "ch1",
+            "ch1.md",
+            "[x](reference/a.md) [x](reference/b.md)",
+            vec![1],
+        ),
+        mk_chap("a", "reference/a.md", "[x](b.md) [x](../ch1.md)", vec![2]),
+        mk_chap("b", "reference/b.md", "[x](a.md)", vec![3]),
+    ]);
+    let config = mk_config(
+        r#"
+        include = ["reference/**/*.md"]
+        exclude = ["reference/b.md"]
+        "#,
+    );
+    let book = process_book(book, &config).unwrap();
+    // Chapters outside of `include` are untouched, and don't count as backlinks.
+    assert_eq!(
+        chapter_content(&book, "ch1.md"),
+        "[x](reference/a.md) [x](reference/b.md)"
+    );
+    let content = chapter_content(&book, "reference/a.md");
+    assert!(content.contains("* [b](b.md)"), "{content}");
+    assert!(!content.contains("* [ch1"), "{content}");
+    // `exclude` takes precedence.
+    assert_eq!(chapter_content(&book, "reference/b.md"), "[x](a.md)");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_non_utf8_paths() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    let path = Path::new(OsStr::from_bytes(b"a/\xffch2.md"));
+    let url = relative_url(Path::new("./a/ch1.md"), Path::new("./b"), "b/ch2.md").unwrap();
+    assert_eq!(url, "../a/ch1.md");
+    let normalized = Path::new(OsStr::from_bytes(b"./a/\xffch2.md"));
+    let err = relative_url(normalized, Path::new("./b"), "b/ch2.md").unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "the path from chapter `b/ch2.md` to `../a/\u{FFFD}ch2.md` is not valid UTF-8"
+    );
+
+    // A chapter with such a path makes the build fail instead of panicking.
+    let ch2 = Chapter::new("ch2", "[x](ch1.md)".into(), path, vec![]);
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "a/ch1.md", "", vec![1]),
+        BookItem::Chapter(ch2),
+    ]);
+    let err = process_book(book, &Config::default()).unwrap_err();
+    assert!(format!("{err:#}").contains("not valid UTF-8"), "{err:#}");
+}
+
+#[test]
+fn test_link_base() {
+    let mk_book = || {
+        Book::new_with_items(vec![
+            mk_chap("ch1", "a/ch1.md", "[x](../b/ch2.md)", vec![1]),
+            mk_chap("ch2", "b/ch2.md", "", vec![2]),
+        ])
+    };
+    let book = process_book(mk_book(), &Config::default()).unwrap();
+    let content = chapter_content(&book, "b/ch2.md");
+    assert!(content.contains("* [ch1](../a/ch1.md)"), "{content}");
+
+    let book = process_book(mk_book(), &mk_config(r#"link_base = "root""#)).unwrap();
+    let content = chapter_content(&book, "b/ch2.md");
+    assert!(content.contains("* [ch1](a/ch1.md)"), "{content}");
+}
+
+#[test]
+fn test_link_extension() {
+    let mk_book = || {
+        Book::new_with_items(vec![
+            mk_chap("ch1", "a/ch1.md", "[x](../b/ch2.md#end)", vec![1]),
+            mk_chap("ch2", "b/ch2.md", "", vec![2]),
+        ])
+    };
+    let content = |link_extension: &str| {
+        let config = mk_config(&format!("link_extension = \"{link_extension}\""));
+        let book = process_book(mk_book(), &config).unwrap();
+        chapter_content(&book, "b/ch2.md").to_owned()
+    };
+    assert!(content("md").contains("* [ch1 → end](../a/ch1.md)"));
+    assert!(content("html").contains("* [ch1 → end](../a/ch1.html)"));
+    assert!(content("none").contains("* [ch1 → end](../a/ch1)"));
+}
+
+#[test]
+fn test_extensions() {
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "a/ch1.markdown", "[x](../b/) [[../c/ch3]]", vec![1]),
+        mk_chap("ch2", "b/README.markdown", "", vec![2]),
+        mk_chap("ch3", "c/ch3.markdown", "", vec![3]),
+    ]);
+    let config = indoc::indoc!(
+        r#"
+        extensions = [".markdown", ".md"]
+        wikilinks = true
+        link_extension = "html"
+        "#
+    );
+    let book = process_book(book, &mk_config(config)).unwrap();
+    let content = chapter_content(&book, "b/README.markdown");
+    assert!(content.contains("* [ch1](../a/ch1.html)"), "{content}");
+    let content = chapter_content(&book, "c/ch3.markdown");
+    assert!(content.contains("* [ch1](../a/ch1.html)"), "{content}");
+}
+
+#[test]
+fn test_embed_metadata() {
+    let book = Book::new_with_items(vec![
+        mk_chap(
+            "ch1",
+            "a/ch1.md",
+            "[x](../b/ch3.md) [y](../b/ch3.md#end)",
+            vec![1],
+        ),
+        mk_chap("ch2", "b/ch2.md", "[x](ch3.md)", vec![2]),
+        mk_chap("ch3", "b/ch3.md", "", vec![3]),
+    ]);
+    let config = mk_config("embed_metadata = true");
+    let book = process_book(book, &config).unwrap();
+    let content = chapter_content(&book, "b/ch3.md").to_owned();
+    let expected = indoc::indoc!(
+        r#"
+        <!-- backlinks -->
+
+        <!-- backlinks: ["../a/ch1.md","ch2.md"] -->
+
+        <div class="mdbook-backlinks">"#
+    );
+    assert!(content.starts_with(&format!("\n\n{expected}")), "{content}");
+
+    // The comment is replaced along with the block.
+    let book = process_book(book, &config).unwrap();
+    assert_eq!(chapter_content(&book, "b/ch3.md"), content);
+}
+
+#[test]
+fn test_index_chapter() {
+    let mk_book = || {
+        Book::new_with_items(vec![
+            mk_chap(
+                "ch1",
+                "a/ch1.md",
+                "[x](../b/ch2.md) [x](../b/ch3.md)",
+                vec![1],
+            ),
+            mk_chap("ch2", "b/ch2.md", "[x](ch3.md)", vec![2]),
+            mk_chap("ch3", "b/ch3.md", "", vec![3]),
+        ])
+    };
+    let config = mk_config(r#"index_chapter = "appendix/backlinks.md""#);
+    let book = process_book(mk_book(), &config).unwrap();
+    let Some(BookItem::Chapter(index)) = book.items.last() else {
+        panic!("no index chapter");
+    };
+    assert_eq!(index.name, "Backlinks index");
+    assert_eq!(index.number, None);
+    assert_eq!(
+        index.path.as_deref(),
+        Some("appendix/backlinks.md".as_ref())
+    );
+    let expected = indoc::indoc!(
+        "
+        # Backlinks index
+
+        ## [ch2](../b/ch2.md)
+
+        * [ch1](../a/ch1.md)
+
+        ## [ch3](../b/ch3.md)
+
+        * [ch1](../a/ch1.md)
+        * [ch2](../b/ch2.md)"
+    );
+    assert_eq!(index.content, expected);
+
+    let config = mk_config(r#"index_chapter = "./b/ch3.md""#);
+    let err = process_book(mk_book(), &config).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "`index_chapter` `./b/ch3.md` is already a chapter of the book"
+    );
+}
+
+#[test]
+fn test_group_anchors() {
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "ch1.md", "[x](ch3.md)", vec![1]),
+        mk_chap("ch2", "ch2.md", "[x](ch3.md#a) [x](ch3.md#b)", vec![2]),
+        mk_chap("ch3", "ch3.md", "# A\n\n# B", vec![3]),
+    ]);
+    let book = process_book(book, &mk_config("group_anchors = true")).unwrap();
+    let content = chapter_content(&book, "ch3.md");
+    let expected = [
+        " > * [ch1](ch1.md)",
+        " > * [ch2](ch2.md)",
+        " >   * [A](ch2.md)",
+        " >   * [B](ch2.md)",
+    ]
+    .join("\n");
+    assert!(content.contains(&expected), "{content}");
+}
+
+#[test]
+fn test_link_types() {
+    let mk_book = || {
+        Book::new_with_items(vec![
+            mk_chap("ch1", "ch1.md", "[x](ch3.md)", vec![1]),
+            mk_chap("ch2", "ch2.md", "[x][ref]\n\n[ref]: ch3.md", vec![2]),
+            mk_chap("ch3", "ch3.md", "", vec![3]),
+        ])
+    };
+    let book = process_book(mk_book(), &Config::default()).unwrap();
+    let content = chapter_content(&book, "ch3.md");
+    assert!(content.contains("* [ch2](ch2.md)"), "{content}");
+
+    let config = mk_config(r#"link_types = ["inline", "collapsed", "shortcut", "autolink"]"#);
+    let book = process_book(mk_book(), &config).unwrap();
+    let content = chapter_content(&book, "ch3.md");
+    assert!(content.contains("* [ch1](ch1.md)"), "{content}");
+    assert!(!content.contains("ch2"), "{content}");
+}
+
+#[test]
+fn test_show_empty() {
+    let mk_book = || {
+        Book::new_with_items(vec![
+            mk_chap("ch1", "ch1.md", "[x](ch2.md)", vec![1]),
+            mk_chap("ch2", "ch2.md", "", vec![2]),
+        ])
+    };
+    let book = process_book(mk_book(), &Config::default()).unwrap();
+    assert_eq!(chapter_content(&book, "ch1.md"), "[x](ch2.md)");
+
+    let book = process_book(mk_book(), &mk_config("show_empty = true")).unwrap();
+    let content = chapter_content(&book, "ch1.md");
+    let expected = [
+        " > #### Backlinks { #backlinks }",
+        " > ",
+        " > No pages link here yet.",
+    ]
+    .join("\n");
+    assert!(content.contains(&expected), "{content}");
+    let content = chapter_content(&book, "ch2.md");
+    assert!(content.contains("* [ch1](ch1.md)"), "{content}");
+    assert!(!content.contains("No pages"), "{content}");
+}
+
+#[test]
+fn test_relationships() {
+    let mk_book = || {
+        Book::new_with_items(vec![
+            mk_chap("ch1", "ch1.md", "[x](ch4.md)", vec![1]),
+            mk_chap(
+                "ch2",
+                "ch2.md",
+                r#"[x](ch4.md "see-also: Details")"#,
+                vec![2],
+            ),
+            mk_chap("ch3", "ch3.md", r#"[x](ch4.md "uses:")"#, vec![3]),
+            mk_chap("ch4", "ch4.md", "", vec![4]),
+        ])
+    };
+    let mut config = mk_config(
+        r#"
+        link_titles = true
+        renderers = ["html", "markdown"]
+        [[relationships]]
+        name = "uses"
+        heading = "Used by"
+        [[relationships]]
+        name = "see-also"
+        heading = "See also"
+        "#,
+    );
+    let book = process_book(mk_book(), &config).unwrap();
+    let content = chapter_content(&book, "ch4.md");
+    let expected = [
+        " > #### Backlinks { #backlinks }",
+        " > ",
+        " > * [ch1](ch1.md)",
+        " > ",
+        " > #### Used by { #mdbook-backlinks-uses-heading }",
+        " > ",
+        " > * [ch3](ch3.md)",
+        " > ",
+        " > #### See also { #mdbook-backlinks-see-also-heading }",
+        " > ",
+        " > * [ch2](ch2.md \"Details\")",
+    ]
+    .join("\n");
+    assert!(content.contains(&expected), "{content}");
+
+    // Heading ids are only meaningful in HTML.
+    config.renderer = "markdown".to_owned();
+    let book = process_book(mk_book(), &config).unwrap();
+    let content = chapter_content(&book, "ch4.md");
+    assert!(content.contains(" > #### Used by\n"), "{content}");
+    assert!(!content.contains("{ #"), "{content}");
+}
+
+#[test]
+fn test_resolve_extensionless() {
+    let book = Book::new_with_items(vec![
+        mk_chap(
+            "ch1",
+            "ch1.md",
+            "[x](a) [y](b#usage) [z](c/) [w](./c/.) [v](d)",
+            vec![1],
+        ),
+        mk_chap("a", "a.md", "", vec![2]),
+        mk_chap("b", "b.md", "", vec![3]),
+        mk_chap("c", "c.md", "", vec![4]),
+        mk_chap("d", "d.md", "", vec![5]),
+        mk_chap("d index", "d/README.md", "", vec![6]),
+    ]);
+    let backlinks = |graph: &LinkGraph, path: &str| {
+        graph.backlinks[&Path::new(path).normalize_path().unwrap()].len()
+    };
+    let graph = LinkGraph::collect(&book, &Config::default()).unwrap();
+    assert_eq!(backlinks(&graph, "a.md"), 0);
+    assert_eq!(backlinks(&graph, "b.md"), 0);
+
+    let graph = LinkGraph::collect(&book, &mk_config("resolve_extensionless = true")).unwrap();
+    assert_eq!(backlinks(&graph, "a.md"), 1);
+    assert_eq!(backlinks(&graph, "b.md"), 1);
+    // Links to a directory don't point to a chapter of the same name.
+    assert_eq!(backlinks(&graph, "c.md"), 0);
+    // A chapter comes before the index of a directory of the same name...
+    assert_eq!(backlinks(&graph, "d.md"), 1);
+    assert_eq!(backlinks(&graph, "d/README.md"), 0);
+    // ... unless the link ends with a `/`.
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "ch1.md", "[x](d/) [y](e)", vec![1]),
+        mk_chap("d", "d.md", "", vec![2]),
+        mk_chap("d index", "d/README.md", "", vec![3]),
+        mk_chap("e index", "e/README.md", "", vec![4]),
+    ]);
+    let graph = LinkGraph::collect(&book, &mk_config("resolve_extensionless = true")).unwrap();
+    assert_eq!(backlinks(&graph, "d.md"), 0);
+    assert_eq!(backlinks(&graph, "d/README.md"), 1);
+    // Without a chapter of the same name, the index is used.
+    assert_eq!(backlinks(&graph, "e/README.md"), 1);
+}
+
+#[test]
+fn test_depth() {
+    // ch4 -> ch2 -> ch1, ch3 -> ch1, ch3 -> ch2, and ch1 -> ch4 closes a cycle.
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "ch1.md", "[x](ch4.md)", vec![1]),
+        mk_chap("ch2", "ch2.md", "[x](ch1.md)", vec![2]),
+        mk_chap("ch3", "ch3.md", "[x](ch1.md) [x](ch2.md)", vec![3]),
+        mk_chap("ch4", "ch4.md", "[x](ch2.md)", vec![4]),
+    ]);
+    let book = process_book(book, &mk_config("depth = 2\nshow_numbers = true")).unwrap();
+    let content = chapter_content(&book, "ch1.md");
+    assert!(
+        content.contains(" > * [2 ch2](ch2.md)\n >   * [4 ch4](ch4.md)\n > * [3 ch3](ch3.md)\n\n"),
+        "{content}"
+    );
+    // Deeper levels stop at the chapters that are already listed.
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "ch1.md", "", vec![1]),
+        mk_chap("ch2", "ch2.md", "[x](ch1.md) [x](ch3.md)", vec![2]),
+        mk_chap("ch3", "ch3.md", "[x](ch2.md)", vec![3]),
+    ]);
+    let book = process_book(book, &mk_config("depth = 5")).unwrap();
+    let content = chapter_content(&book, "ch1.md");
+    assert!(
+        content.contains(" > * [ch2](ch2.md)\n >   * [ch3](ch3.md)\n\n"),
+        "{content}"
+    );
+}
+
+#[test]
+fn test_item_format() {
+    let book = Book::new_with_items(vec![
+        mk_chap("ch1", "ch1.md", "[x](ch3.md) [x](ch3.md)", vec![1, 2]),
+        mk_chap("ch2", "ch2.md", "[x](ch3.md#usage)", vec![]),
+        mk_chap("ch3", "ch3.md", "## Usage", vec![3]),
+    ]);
+    let config = mk_config(r#"item_format = "{number} {name} ({count}) {{{anchor}}} <{url}>""#);
+    let book = process_book(book, &config).unwrap();
+    let content = chapter_content(&book, "ch3.md");
+    assert!(
+        content.contains("* [ ch2 (1) {Usage} <ch2.md>](ch2.md)"),
+        "{content}"
+    );
+    assert!(
+        content.contains("* [1.2 ch1 (2) {} <ch1.md>](ch1.md)"),
+        "{content}"
+    );
+
+    let error = |format: &str| {
+        toml::from_str::<Config>(&format!("item_format = {format:?}"))
+            .unwrap_err()
+            .to_string()
+    };
+    assert!(
+        error("{name} {title}").contains(
+            "unknown placeholder `{title}` in item_format `{name} {title}`, expected one of \
+             `{name}`, `{number}`, `{url}`, `{count}`, `{anchor}`"
+        ),
+        "{}",
+        error("{name} {title}")
+    );
+    assert!(error("{name").contains("unmatched `{`"));
+    assert!(error("name}").contains("unmatched `}`"));
+}
+
+#[test]
+fn test_anchor_to_source() {
+    let book = Book::new_with_items(vec![
+        mk_chap(
+            "ch1",
+            "a/ch1.md",
+            "[x](../ch3.md)\n\n# Usage\n\n## Details\n\n[y](../ch3.md)",
+            vec![1],
+        ),
+        mk_chap(
+            "ch2",
+            "ch2.md",
+            "# Intro {#start}\n\n# Intro\n\n# Intro\n\n[x](ch3.md)",
+            vec![2],
+        ),
+        mk_chap("ch3", "ch3.md", "", vec![3]),
+    ]);
+    let book = process_book(book, &mk_config("anchor_to_source = true")).unwrap();
+    let content = chapter_content(&book, "ch3.md");
+    // Links before the first heading point to the top of the chapter.
+    assert!(content.contains("* [ch1](a/ch1.md)\n"), "{content}");
+    assert!(content.contains("* [ch1](a/ch1.md#details)\n"), "{content}");
+    assert!(content.contains("* [ch2](ch2.md#intro-1)\n"), "{content}");
+}
+
+#[test]
+fn test_container() {
+    let mk_book = || {
+        Book::new_with_items(vec![
+            mk_chap("ch1", "ch1.md", "[x](ch2.md)", vec![1]),
+            mk_chap("ch2", "ch2.md", "Text", vec![2]),
+        ])
+    };
+    let content = |container: &str| {
+        let config = mk_config(&format!("container = \"{container}\"\ncss_class = \"\""));
+        let book = process_book(mk_book(), &config).unwrap();
+        chapter_content(&book, "ch2.md").to_owned()
+    };
+    assert!(
+        content("blockquote").contains("---\n\n > \n > #### Backlinks"),
+        "{}",
+        content("blockquote")
+    );
+    let expected = indoc::indoc!(
+        "
+        Text
+
+        <!-- backlinks -->
+
+        ---
+
+        #### Backlinks { #backlinks }
+
+        * [ch1](ch1.md)
+
+        <!-- /backlinks -->"
+    );
+    assert_eq!(content("section"), expected);
+    let expected = indoc::indoc!(
+        "
+        Text
+
+        <!-- backlinks -->
+
+        * [ch1](ch1.md)
+
+        <!-- /backlinks -->"
+    );
+    assert_eq!(content("none"), expected);
+}